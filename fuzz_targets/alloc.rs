@@ -0,0 +1,151 @@
+//! Per-input allocation budget.
+//!
+//! The target templates install `BudgetAllocator` as the global allocator and run every input
+//! through `run_with_budget`. An input that makes the target hold more than the budget at once
+//! is reported along with the input and aborts the process, which the engines save as a crash,
+//! instead of being killed as an OOM without a useful reproducer.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    io::Write,
+    process, ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+/// Environment variable holding the budget in MB, 0 disables the check
+pub const ALLOC_BUDGET_ENV: &str = "TARI_FUZZ_ALLOC_BUDGET_MB";
+/// Budget used when the environment variable is not set
+pub const DEFAULT_ALLOC_BUDGET_MB: usize = 1024;
+
+const UNSET: usize = usize::MAX;
+
+static BUDGET: AtomicUsize = AtomicUsize::new(UNSET);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static ARMED: AtomicBool = AtomicBool::new(false);
+static INPUT: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+static INPUT_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting the bytes allocated while an input runs
+pub struct BudgetAllocator;
+
+impl BudgetAllocator {
+    #[inline]
+    fn charge(&self, size: usize) {
+        if !ARMED.load(Ordering::Relaxed) {
+            return;
+        }
+        let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+        let budget = BUDGET.load(Ordering::Relaxed);
+        if budget != 0 && live > budget {
+            budget_exceeded(size, live, budget);
+        }
+    }
+
+    #[inline]
+    fn refund(&self, size: usize) {
+        if ARMED.load(Ordering::Relaxed) {
+            // Memory allocated before the input started is freed without having been charged
+            let _ = LIVE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+                Some(live.saturating_sub(size))
+            });
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for BudgetAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.charge(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.charge(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.refund(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            self.charge(new_size - layout.size());
+        } else {
+            self.refund(layout.size() - new_size);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Report the allocation that broke the budget and the input that caused it, then abort
+#[cold]
+#[inline(never)]
+fn budget_exceeded(size: usize, live: usize, budget: usize) -> ! {
+    // Stop counting first, reporting allocates
+    ARMED.store(false, Ordering::SeqCst);
+    let input = unsafe {
+        let input = INPUT.load(Ordering::SeqCst);
+        if input.is_null() {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(input, INPUT_LEN.load(Ordering::SeqCst))
+        }
+    };
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    let _ = writeln!(
+        stderr,
+        "==ERROR: allocation budget exceeded: allocating {} bytes with {} bytes live, budget {} bytes ({} MB, set {})",
+        size,
+        live - size,
+        budget,
+        budget >> 20,
+        ALLOC_BUDGET_ENV
+    );
+    let _ = writeln!(
+        stderr,
+        "input ({} bytes): {}",
+        input.len(),
+        ::hex::encode(input)
+    );
+    process::abort()
+}
+
+/// The budget in bytes, read from the environment the first time
+fn budget() -> usize {
+    let budget = BUDGET.load(Ordering::Relaxed);
+    if budget != UNSET {
+        return budget;
+    }
+    let mb = env::var(ALLOC_BUDGET_ENV)
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_ALLOC_BUDGET_MB);
+    let budget = mb.saturating_mul(1 << 20);
+    BUDGET.store(budget, Ordering::Relaxed);
+    budget
+}
+
+/// Stops counting when the input is done, including when the target panics
+struct Armed;
+
+impl Drop for Armed {
+    fn drop(&mut self) {
+        ARMED.store(false, Ordering::SeqCst);
+        INPUT.store(ptr::null_mut(), Ordering::SeqCst);
+        INPUT_LEN.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Run `f` on one input with the allocation budget enforced
+pub fn run_with_budget<F: FnOnce()>(data: &[u8], f: F) {
+    let _ = budget();
+    INPUT.store(data.as_ptr() as *mut u8, Ordering::SeqCst);
+    INPUT_LEN.store(data.len(), Ordering::SeqCst);
+    LIVE.store(0, Ordering::SeqCst);
+    ARMED.store(true, Ordering::SeqCst);
+    let _armed = Armed;
+    f();
+}
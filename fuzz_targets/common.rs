@@ -5,6 +5,10 @@ use proptest::{
     strategy::{Strategy, ValueTree},
     test_runner::TestRunner,
 };
+use std::{
+    fmt::Debug,
+    panic::{self, AssertUnwindSafe},
+};
 // credit libra-fuzzer
 
 #[derive(Default)]
@@ -32,3 +36,341 @@ impl SeedGen {
             .current()
     }
 }
+
+/// Reads structured values out of raw fuzzer input.
+///
+/// Reads past the end of the input yield zeroes, so every input decodes to something and no
+/// time is wasted on inputs that would be rejected up front.
+pub struct DataReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> DataReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Number of input bytes not consumed yet.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Take up to `len` bytes, fewer if the input runs out.
+    pub fn read_bytes(&mut self, len: usize) -> &'a [u8] {
+        let end = self.pos + len.min(self.remaining());
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        bytes
+    }
+
+    /// Take everything that is left.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let len = self.remaining();
+        self.read_bytes(len)
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        self.read_bytes(1).first().cloned().unwrap_or(0)
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.read_u8() & 1 == 1
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        let mut buf = [0u8; 2];
+        let bytes = self.read_bytes(2);
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u16::from_le_bytes(buf)
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        let mut buf = [0u8; 4];
+        let bytes = self.read_bytes(4);
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(buf)
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        let mut buf = [0u8; 8];
+        let bytes = self.read_bytes(8);
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    /// A length-prefixed byte vector of at most `max_len` bytes.
+    pub fn read_vec(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.read_u16() as usize % (max_len + 1);
+        self.read_bytes(len).to_vec()
+    }
+
+    /// A length-prefixed string of at most `max_len` bytes, invalid UTF-8 is replaced.
+    pub fn read_string(&mut self, max_len: usize) -> String {
+        String::from_utf8_lossy(&self.read_vec(max_len)).into_owned()
+    }
+}
+
+// Property oracles
+//
+// Targets compose these instead of asserting by hand. Each one is kept out of line and panics with
+// its own label, so a crash's stack and message say which property broke and where.
+
+/// `decode(encode(value)) == value`. A failed decode counts as a violation.
+#[inline(never)]
+pub fn round_trip<T, E, Enc, Dec>(label: &str, value: &T, encode: Enc, decode: Dec)
+where
+    T: PartialEq + Debug,
+    Enc: FnOnce(&T) -> E,
+    Dec: FnOnce(E) -> Option<T>,
+{
+    match decode(encode(value)) {
+        Some(ref decoded) if decoded == value => {}
+        decoded => panic!(
+            "oracle round_trip violated ({}): {:?} came back as {:?}",
+            label, value, decoded
+        ),
+    }
+}
+
+/// `f(f(value)) == f(value)`. Returns `f(value)`.
+#[inline(never)]
+pub fn idempotent<T, F>(label: &str, value: &T, f: F) -> T
+where
+    T: PartialEq + Debug,
+    F: Fn(&T) -> T,
+{
+    let once = f(value);
+    let twice = f(&once);
+    if once != twice {
+        panic!(
+            "oracle idempotent violated ({}): {:?} became {:?}",
+            label, once, twice
+        );
+    }
+    once
+}
+
+/// `f(a, b) == f(b, a)`. Returns `f(a, b)`.
+#[inline(never)]
+pub fn commutative<A, R, F>(label: &str, a: &A, b: &A, f: F) -> R
+where
+    A: Debug,
+    R: PartialEq + Debug,
+    F: Fn(&A, &A) -> R,
+{
+    let ab = f(a, b);
+    let ba = f(b, a);
+    if ab != ba {
+        panic!(
+            "oracle commutative violated ({}): f({:?}, {:?}) = {:?} but f({:?}, {:?}) = {:?}",
+            label, a, b, ab, b, a, ba
+        );
+    }
+    ab
+}
+
+/// An operation on input known to be valid must succeed. Returns the value.
+#[inline(never)]
+pub fn never_errors_on_valid<T, E: Debug>(label: &str, result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => panic!(
+            "oracle never_errors_on_valid violated ({}): {:?}",
+            label, err
+        ),
+    }
+}
+
+/// Run `f`, returning `None` if it panics. The engines install panic hooks that abort, so a
+/// silent hook stands in for theirs while `f` runs.
+pub fn catch_panic<T, F: FnOnce() -> T>(f: F) -> Option<T> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result.ok()
+}
+
+/// An arithmetic operator agrees with the `checked_*` result of the same operation on the
+/// underlying integers. On overflow (`checked` is `None`) it must give `overflowed`, the wrapping
+/// or saturating result, whichever the type defines. `op` runs unguarded, so an overflow panic
+/// is a finding like any other.
+#[inline(never)]
+pub fn matches_checked<T, F>(label: &str, checked: Option<T>, overflowed: T, op: F)
+where
+    T: PartialEq + Debug,
+    F: FnOnce() -> T,
+{
+    let result = op();
+    let expected = checked.unwrap_or(overflowed);
+    if result != expected {
+        panic!(
+            "oracle matches_checked violated ({}): got {:?}, expected {:?}",
+            label, result, expected
+        );
+    }
+}
+
+/// Something derived from `input_len` bytes of input must not grow past `factor` times the
+/// input plus `slack`. Anything bigger usually means a length field was trusted blindly.
+#[inline(never)]
+pub fn bounded_allocation(label: &str, input_len: usize, size: usize, factor: usize, slack: usize) {
+    let limit = input_len.saturating_mul(factor).saturating_add(slack);
+    if size > limit {
+        panic!(
+            "oracle bounded_allocation violated ({}): {} input bytes produced {} bytes, limit {}",
+            label, input_len, size, limit
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn data_reader_zero_fills_past_the_end() {
+        let mut reader = DataReader::new(&[0x01, 0x02, 0x03]);
+        assert_eq!(reader.read_u16(), 0x0201);
+        // One byte left, the other three read as zero
+        assert_eq!(reader.read_u32(), 0x03);
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8(), 0);
+        assert!(!reader.read_bool());
+        assert_eq!(reader.read_u64(), 0);
+        assert_eq!(reader.read_vec(16), Vec::<u8>::new());
+        assert_eq!(reader.read_string(16), "");
+        assert_eq!(reader.rest(), &[] as &[u8]);
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn data_reader_reads_length_prefixed_vecs() {
+        let mut reader = DataReader::new(&[0x03, 0x00, b'a', b'b', b'c', b'd']);
+        assert_eq!(reader.read_vec(16), b"abc".to_vec());
+        assert_eq!(reader.rest(), b"d");
+
+        // The length is taken modulo `max_len + 1`, and cut short by the end of input
+        let mut reader = DataReader::new(&[0x07, 0x00, b'a', b'b']);
+        assert_eq!(reader.read_vec(3), b"ab".to_vec());
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn round_trip_holds() {
+        round_trip(
+            "u64 to_string",
+            &42u64,
+            |x| x.to_string(),
+            |s| s.parse().ok(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "oracle round_trip violated (lossy)")]
+    fn round_trip_catches_changed_values() {
+        round_trip("lossy", &300u64, |x| *x as u8, |b| Some(b as u64));
+    }
+
+    #[test]
+    #[should_panic(expected = "oracle round_trip violated (failing decode)")]
+    fn round_trip_catches_failed_decodes() {
+        round_trip("failing decode", &1u8, |x| *x, |_| None::<u8>);
+    }
+
+    #[test]
+    fn idempotent_returns_first_application() {
+        assert_eq!(idempotent("abs", &-5i64, |x| x.abs()), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "oracle idempotent violated (increment)")]
+    fn idempotent_catches_repeated_changes() {
+        idempotent("increment", &0u8, |x| x + 1);
+    }
+
+    #[test]
+    fn commutative_returns_result() {
+        assert_eq!(commutative("add", &2u8, &3u8, |a, b| a + b), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "oracle commutative violated (sub)")]
+    fn commutative_catches_order_dependence() {
+        commutative("sub", &2i8, &3i8, |a, b| a - b);
+    }
+
+    #[test]
+    fn matches_checked_accepts_checked_and_overflowed_results() {
+        let (a, b) = (200u8, 100u8);
+        matches_checked("wrapping", a.checked_add(b), a.wrapping_add(b), || {
+            a.wrapping_add(b)
+        });
+        matches_checked("saturating", a.checked_add(b), a.saturating_add(b), || {
+            a.saturating_add(b)
+        });
+        matches_checked("in range", b.checked_add(b), 0, || b + b);
+    }
+
+    #[test]
+    #[should_panic(expected = "oracle matches_checked violated (wrong overflow)")]
+    fn matches_checked_catches_wrong_overflow() {
+        let (a, b) = (200u8, 100u8);
+        matches_checked(
+            "wrong overflow",
+            a.checked_add(b),
+            a.saturating_add(b),
+            || a.wrapping_add(b),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "attempt to add with overflow")]
+    fn matches_checked_does_not_guard_op() {
+        let (a, b) = (200u8, 100u8);
+        matches_checked("panicking", a.checked_add(b), a.wrapping_add(b), || {
+            a.checked_add(b).expect("attempt to add with overflow")
+        });
+    }
+
+    #[test]
+    fn bounded_allocation_allows_up_to_limit() {
+        bounded_allocation("at limit", 10, 25, 2, 5);
+        bounded_allocation("no overflow", usize::MAX, usize::MAX, 2, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "oracle bounded_allocation violated (over limit)")]
+    fn bounded_allocation_catches_excess() {
+        bounded_allocation("over limit", 10, 26, 2, 5);
+    }
+
+    /// Set by the hook `catch_panic` must restore
+    static OUTER_HOOK_RAN: AtomicBool = AtomicBool::new(false);
+
+    #[test]
+    fn catch_panic_restores_previous_hook() {
+        let original = panic::take_hook();
+        panic::set_hook(Box::new(|info| {
+            if info.payload().downcast_ref::<&str>() == Some(&"probe") {
+                OUTER_HOOK_RAN.store(true, Ordering::SeqCst);
+            }
+        }));
+
+        assert_eq!(catch_panic(|| 7), Some(7));
+        assert_eq!(catch_panic(|| -> u8 { panic!("probe") }), None);
+        // The silent hook stood in while `f` ran
+        assert!(!OUTER_HOOK_RAN.load(Ordering::SeqCst));
+
+        let _ = panic::catch_unwind(|| panic!("probe"));
+        let restored = OUTER_HOOK_RAN.load(Ordering::SeqCst);
+        panic::set_hook(original);
+        assert!(restored, "catch_panic did not restore the previous hook");
+    }
+}
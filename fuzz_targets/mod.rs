@@ -1,36 +1,143 @@
 extern crate digest;
+extern crate hex;
+#[macro_use]
+extern crate lazy_static;
 extern crate log;
 extern crate proptest;
+extern crate prost;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate tari_crypto;
 extern crate tari_mmr;
 extern crate tari_core;
+#[cfg(feature = "differential")]
+extern crate tari_core_baseline;
+#[cfg(feature = "differential")]
+extern crate tari_mmr_baseline;
+extern crate tari_utilities;
 use log::{info, trace, warn};
 
+/// A fuzz target, called with one input
+pub type TargetFn = fn(&[u8]);
+
+/// Pairs target names with their functions, so targets can be run in-process
+macro_rules! target_fns {
+    ($($name:ident),* $(,)*) => {
+        &[$((stringify!($name), $name as ::TargetFn)),*]
+    };
+}
+
+/// Registers a group of targets: one function per line running `$harness` on the input, with
+/// their names in `$names` and their functions in `$fns` for `TARGET_FNS`
+macro_rules! register_targets {
+    ($names:ident, $fns:ident; $($name:ident => $harness:expr;)*) => {
+        $(
+            pub fn $name(data: &[u8]) {
+                $harness(data);
+            }
+        )*
+
+        /// Names of the targets registered in this group
+        pub const $names: &[&str] = &[$(stringify!($name)),*];
+
+        /// Functions of the targets registered in this group
+        pub const $fns: &[(&str, ::TargetFn)] = target_fns![$($name),*];
+    };
+}
+
+pub mod alloc;
 pub mod common;
 pub mod tari_util;
+pub mod tari_core_blocks;
+pub mod tari_core_chain;
+pub mod tari_core_encoding;
+pub mod tari_core_misc;
 pub mod tari_core_mmr;
+pub mod tari_core_pow;
 pub mod tari_core_transactions;
+pub mod tari_diff;
 
 /// Tari Core targets
+pub use tari_core_blocks::*;
+pub use tari_core_chain::*;
+pub use tari_core_encoding::*;
+pub use tari_core_misc::*;
 pub use tari_core_mmr::*;
+pub use tari_core_pow::*;
 pub use tari_core_transactions::*;
 /// Tari Util targets
 pub use tari_util::*;
+/// Differential targets, comparing against a baseline Tari revision
+pub use tari_diff::*;
 
 /// Tari Crypto targets
 
-/// Targets
-pub const TARGETS: &[&str] = &[
-    "util_to_hex",
-    "util_bytes_to_bits",
-    "util_from_hex",
-    "util_to_hex_multiple",
-    "core_trx_create_coinbase",
-    "core_trx_with_maturity",
-    "mmr_push_bytes",
-
+/// Targets written out by hand; the modules register the rest through their macros
+const BASE_TARGET_FNS: &[(&str, TargetFn)] = target_fns![
+    util_to_hex,
+    util_bytes_to_bits,
+    util_from_hex,
+    util_to_hex_multiple,
+    core_trx_create_coinbase,
+    core_trx_with_maturity,
+    mmr_push_bytes,
+    util_hex_round_trip,
+    util_hex_differential,
+    util_bits_round_trip,
+    util_uint_bits_round_trip,
+    util_message_format_round_trip,
+    util_message_format_garbage,
+    util_extend_bytes,
+    mmr_proof_verify,
+    mmr_proof_forged,
+    mmr_mutable_ops,
+    mmr_change_tracker_ops,
+    mmr_pruned_root,
+    mmr_pruned_mutable_root,
+    mmr_leaf_hashes_range,
+    mmr_bitmap_deserialize,
+    mmr_leaf_nodes_deserialize,
+    core_trx_validate,
+    core_trx_sender_protocol,
+    core_pow_difficulty_arith,
+    core_pow_lwma,
+    core_pow_achieved_difficulty,
+    core_fee_calculate,
+    core_micro_tari_arith,
+    core_chain_mempool_ops,
 ];
 
+lazy_static! {
+    /// Targets built into this crate, with the functions they run
+    pub static ref TARGET_FNS: Vec<(&'static str, TargetFn)> = {
+        let mut targets = BASE_TARGET_FNS.to_vec();
+        targets.extend_from_slice(BYTE_ARRAY_TARGET_FNS);
+        targets.extend_from_slice(HEX_TRAIT_TARGET_FNS);
+        targets.extend_from_slice(ENCODING_TARGET_FNS);
+        #[cfg(feature = "differential")]
+        targets.extend_from_slice(DIFF_TARGET_FNS);
+        targets
+    };
+
+    /// Targets
+    pub static ref TARGETS: Vec<&'static str> = {
+        let mut targets: Vec<&'static str> = TARGET_FNS.iter().map(|&(name, _)| name).collect();
+        // Differential targets are listed even when they are not built in, the runner enables them
+        #[cfg(not(feature = "differential"))]
+        targets.extend_from_slice(DIFF_TARGETS);
+        targets
+    };
+}
+
+/// The function running `target`, if it is built into this crate
+pub fn target_fn(target: &str) -> Option<TargetFn> {
+    TARGET_FNS
+        .iter()
+        .find(|&&(name, _)| name == target)
+        .map(|&(_, f)| f)
+}
+
 pub fn list_targets() {
     trace!("Getting a list of targets");
     for target in &*TARGETS {
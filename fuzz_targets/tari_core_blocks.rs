@@ -0,0 +1,16 @@
+use tari_core::{
+    blocks::{Block, BlockHeader},
+    consensus::{ConsensusManagerBuilder, Network},
+};
+
+/// A valid block `height` blocks past the genesis block, the source of seeds for the block
+/// targets
+pub fn seed_block(height: u64) -> Block {
+    let mut block = ConsensusManagerBuilder::new(Network::LocalNet)
+        .build()
+        .get_genesis_block();
+    for _ in 0..height {
+        block.header = BlockHeader::from_previous(&block.header);
+    }
+    block
+}
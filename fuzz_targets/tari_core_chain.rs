@@ -0,0 +1,243 @@
+use common::DataReader;
+use std::{collections::BTreeSet, sync::Arc};
+use tari_core::{
+    blocks::{Block, BlockHeader},
+    chain_storage::{BlockchainDatabase, BlockchainDatabaseConfig, MemoryDatabase, Validators},
+    consensus::{ConsensusManagerBuilder, Network},
+    mempool::{Mempool, MempoolConfig, MempoolValidators, TxStorageResponse},
+    transactions::{
+        aggregated_body::AggregateBody,
+        helpers::{create_utxo, spend_utxos, TransactionSchema},
+        tari_amount::MicroTari,
+        transaction::{OutputFeatures, Transaction, UnblindedOutput},
+        types::{CryptoFactories, HashDigest, HashOutput},
+    },
+    validation::{mocks::MockValidator, transaction_validators::TxInputAndMaturityValidator},
+};
+use tari_crypto::tari_utilities::Hashable;
+
+type Db = BlockchainDatabase<MemoryDatabase<HashDigest>>;
+
+/// Upper bound on operations per input, each one replays the whole chain
+const MAX_OPERATIONS: usize = 64;
+
+/// Replay the chain the database reports from genesis to tip and check its UTXO set against it.
+///
+/// Validation is mocked out, so whether a block spends outputs that exist is not checked here.
+/// The database must keep every output the replay leaves unspent, and no other output: neither
+/// one the replay spent nor one from `created` that never made it into the chain.
+fn check_chain(db: &Db, created: &BTreeSet<HashOutput>) {
+    let height = db.get_height().unwrap().unwrap_or(0);
+    let mut utxos = BTreeSet::new();
+    for h in 0..=height {
+        let historical = db.fetch_block(h).unwrap();
+        let block = historical.block();
+        for output in block.body.outputs() {
+            utxos.insert(output.hash());
+        }
+        for input in block.body.inputs() {
+            utxos.remove(&input.hash());
+        }
+    }
+    for hash in utxos.iter().chain(created) {
+        assert_eq!(
+            is_utxo(db, hash.clone()),
+            utxos.contains(hash),
+            "UTXO set disagrees with the replayed chain"
+        );
+    }
+}
+
+/// Whether `hash` is unspent according to the database the mempool validates against
+fn is_utxo(db: &Db, hash: HashOutput) -> bool {
+    db.is_utxo(hash).unwrap()
+}
+
+/// Mine `txs` in a block on top of the current tip
+fn mine(db: &Db, txs: &[Transaction], nonce: u64) -> Option<Arc<Block>> {
+    let tip = db.fetch_tip_header().unwrap();
+    let mut header = BlockHeader::from_previous(&tip);
+    header.nonce = nonce;
+    let (mut inputs, mut outputs, mut kernels) = (Vec::new(), Vec::new(), Vec::new());
+    for tx in txs {
+        inputs.extend(tx.body.inputs().iter().cloned());
+        outputs.extend(tx.body.outputs().iter().cloned());
+        kernels.extend(tx.body.kernels().iter().cloned());
+    }
+    let mut body = AggregateBody::new(inputs, outputs, kernels);
+    body.sort();
+    let block = Arc::new(Block { header, body });
+    db.add_block(block.clone()).ok().map(|_| block)
+}
+
+/// Run fuzzed operation sequences against an in-memory blockchain database and a mempool.
+///
+/// Operations mint fresh outputs, submit transactions spending known (possibly already spent
+/// or unconfirmed) outputs to the mempool, mine pending transactions or the mempool contents,
+/// rewind the chain to re-org it and query both. Block validation is mocked out, so blocks of
+/// conflicting transactions are stored as they are and only the storage and mempool layers are
+/// under test: after every operation the UTXO set must match a replay of the stored chain, and
+/// the mempool, which checks inputs against the database, must only accept and hand out
+/// transactions spending unspent outputs. Failing to insert a transaction is fine, as long as
+/// the transaction does not end up in the unconfirmed pool anyway.
+pub fn core_chain_mempool_ops(data: &[u8]) {
+    let factories = CryptoFactories::default();
+    let rules = ConsensusManagerBuilder::new(Network::LocalNet).build();
+    let validators = Validators::new(
+        MockValidator::new(true),
+        MockValidator::new(true),
+        MockValidator::new(true),
+    );
+    let db = BlockchainDatabase::new(
+        MemoryDatabase::<HashDigest>::default(),
+        &rules,
+        validators,
+        BlockchainDatabaseConfig::default(),
+    )
+    .unwrap();
+    let mempool = Mempool::new(
+        db.clone(),
+        MempoolConfig::default(),
+        MempoolValidators::new(
+            TxInputAndMaturityValidator {},
+            TxInputAndMaturityValidator {},
+        ),
+    );
+
+    let mut reader = DataReader::new(data);
+    // Outputs we hold the keys to, whether or not they are still spendable
+    let mut wallet: Vec<UnblindedOutput> = Vec::new();
+    let mut pending: Vec<Transaction> = Vec::new();
+    // Every output ever created, spent or not, mined or not
+    let mut created: BTreeSet<HashOutput> = BTreeSet::new();
+    check_chain(&db, &created);
+
+    for _ in 0..MAX_OPERATIONS {
+        if reader.is_empty() {
+            break;
+        }
+        match reader.read_u8() % 6 {
+            0 => {
+                // Mint fresh outputs
+                let mut outputs = Vec::new();
+                for _ in 0..=reader.read_u8() % 4 {
+                    let value = MicroTari::from(10_000 + reader.read_u32() as u64);
+                    let (_, utxo) = create_utxo(value, &factories, None);
+                    let output = utxo.as_transaction_output(&factories).unwrap();
+                    created.insert(output.hash());
+                    outputs.push(output);
+                    wallet.push(utxo);
+                }
+                let tip = db.fetch_tip_header().unwrap();
+                let body = AggregateBody::new(Vec::new(), outputs, Vec::new());
+                let block = Block {
+                    header: BlockHeader::from_previous(&tip),
+                    body,
+                };
+                assert!(
+                    db.add_block(Arc::new(block)).is_ok(),
+                    "minting block rejected"
+                );
+            }
+            1 if !wallet.is_empty() => {
+                // Spend one or two distinct known outputs through the mempool. The mempool only
+                // checks inputs against the database, so a transaction spending the same output
+                // twice would be the harness's fault rather than the mempool's.
+                let first = reader.read_u8() as usize % wallet.len();
+                let mut from = vec![wallet[first].clone()];
+                if reader.read_bool() && wallet.len() > 1 {
+                    let offset = 1 + reader.read_u8() as usize % (wallet.len() - 1);
+                    from.push(wallet[(first + offset) % wallet.len()].clone());
+                }
+                let total: u64 = from.iter().map(|u| u64::from(u.value)).sum();
+                let fee = MicroTari::from(100 + reader.read_u8() as u64);
+                let schema = TransactionSchema {
+                    from,
+                    to: vec![MicroTari::from(total / 2)],
+                    fee,
+                    lock_height: 0,
+                    features: OutputFeatures::default(),
+                };
+                let (tx, outputs, _) = spend_utxos(schema);
+                for output in tx.body.outputs() {
+                    created.insert(output.hash());
+                }
+                let excess_sig = tx.body.kernels()[0].excess_sig.clone();
+                let response = match mempool.insert(Arc::new(tx.clone())) {
+                    Ok(response) => response,
+                    Err(_) => {
+                        assert_ne!(
+                            mempool.has_tx_with_excess_sig(excess_sig).unwrap(),
+                            TxStorageResponse::UnconfirmedPool,
+                            "mempool kept a transaction it failed to insert"
+                        );
+                        TxStorageResponse::NotStored
+                    }
+                };
+                if response == TxStorageResponse::UnconfirmedPool {
+                    for input in tx.body.inputs() {
+                        assert!(
+                            is_utxo(&db, input.hash()),
+                            "mempool accepted a missing input"
+                        );
+                    }
+                }
+                wallet.extend(outputs);
+                pending.push(tx);
+            }
+            2 if !pending.is_empty() => {
+                // Mine arbitrary pending transactions, which may conflict
+                let mut txs = Vec::new();
+                for _ in 0..=reader.read_u8() % 3 {
+                    txs.push(pending[reader.read_u8() as usize % pending.len()].clone());
+                }
+                if let Some(block) = mine(&db, &txs, reader.read_u64()) {
+                    mempool.process_published_block(block).unwrap();
+                }
+            }
+            3 => {
+                // Mine whatever the mempool hands out, which must never conflict
+                let txs = mempool.retrieve(reader.read_u32() as u64).unwrap();
+                let mut spent = BTreeSet::new();
+                for tx in &txs {
+                    for input in tx.body.inputs() {
+                        assert!(
+                            is_utxo(&db, input.hash()),
+                            "mempool returned a spent input"
+                        );
+                        assert!(
+                            spent.insert(input.hash()),
+                            "mempool returned a double spend"
+                        );
+                    }
+                }
+                let txs: Vec<Transaction> = txs.iter().map(|tx| (**tx).clone()).collect();
+                let block = mine(&db, &txs, reader.read_u64()).expect("mempool block rejected");
+                mempool.process_published_block(block).unwrap();
+            }
+            4 => {
+                // Remove blocks from the tip; mining afterwards builds a competing branch
+                let height = db.get_height().unwrap().unwrap_or(0);
+                let target = height.saturating_sub(1 + reader.read_u8() as u64 % 4);
+                let removed = db.rewind_to_height(target).unwrap();
+                mempool.process_reorg(removed, Vec::new()).unwrap();
+                assert_eq!(db.get_height().unwrap().unwrap_or(0), target);
+            }
+            _ => {
+                // Queries
+                let _ = mempool.stats().unwrap();
+                if !pending.is_empty() {
+                    let tx = &pending[reader.read_u8() as usize % pending.len()];
+                    let _ = mempool
+                        .has_tx_with_excess_sig(tx.body.kernels()[0].excess_sig.clone())
+                        .unwrap();
+                }
+                if !wallet.is_empty() {
+                    let utxo = &wallet[reader.read_u8() as usize % wallet.len()];
+                    let _ = is_utxo(&db, utxo.as_transaction_output(&factories).unwrap().hash());
+                }
+            }
+        }
+        check_chain(&db, &created);
+    }
+}
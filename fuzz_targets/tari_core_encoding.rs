@@ -0,0 +1,257 @@
+use common::{bounded_allocation, idempotent, never_errors_on_valid, round_trip};
+use prost::Message;
+use std::convert::TryFrom;
+use tari_core::{
+    blocks::{Block, BlockHeader},
+    proof_of_work::ProofOfWork,
+    proto::core as block_proto,
+    transactions::{
+        aggregated_body::AggregateBody,
+        proto::types as trx_proto,
+        transaction::{
+            KernelFeatures, OutputFeatures, Transaction, TransactionInput, TransactionKernel,
+            TransactionOutput,
+        },
+        types::{Commitment, PublicKey, Signature},
+    },
+};
+use tari_core_blocks::seed_block;
+use tari_core_transactions::seed_transaction;
+use tari_crypto::tari_utilities::Hashable;
+use tari_utilities::message_format::MessageFormat;
+
+/// Re-encoding a decoded value may add defaults for fields the input left out, but must stay
+/// linear in the input size. Anything bigger means a length field was trusted blindly.
+const MAX_ENCODING_GROWTH: usize = 16;
+const ENCODING_SLACK: usize = 4096;
+
+/// Decode `T` from arbitrary binary and JSON input; all that is checked is that garbage is
+/// rejected without panicking
+pub fn encoding_decode<T: MessageFormat>(data: &[u8]) {
+    let _ = T::from_binary(data);
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = T::from_json(s);
+    }
+}
+
+/// Anything `T` decodes must encode -> decode -> encode to the same bytes, in both the binary
+/// and the JSON format, and the JSON encoding must decode to the same binary encoding. Returns
+/// the decoded value for further checks.
+pub fn encoding_idempotent<T: MessageFormat>(data: &[u8]) -> Option<T> {
+    let value = T::from_binary(data).ok()?;
+
+    let encoded = never_errors_on_valid("to_binary", value.to_binary());
+    idempotent("binary encoding", &encoded, |bytes| {
+        let decoded = never_errors_on_valid("from_binary", T::from_binary(bytes));
+        never_errors_on_valid("to_binary", decoded.to_binary())
+    });
+
+    let json = never_errors_on_valid("to_json", value.to_json());
+    idempotent("JSON encoding", &json, |json| {
+        let decoded = never_errors_on_valid("from_json", T::from_json(json));
+        never_errors_on_valid("to_json", decoded.to_json())
+    });
+    round_trip(
+        "binary -> JSON -> binary",
+        &encoded,
+        |bytes| T::from_binary(bytes).ok()?.to_json().ok(),
+        |json| T::from_json(&json?).ok()?.to_binary().ok(),
+    );
+    Some(value)
+}
+
+/// Anything the protobuf message `P` decodes and converts to `T` must convert back and
+/// encode -> decode -> encode to the same bytes. Returns the decoded value for further checks.
+pub fn encoding_proto_idempotent<T, P>(data: &[u8]) -> Option<T>
+where
+    T: TryFrom<P> + Clone,
+    P: Message + Default + From<T>,
+{
+    let value = T::try_from(P::decode(data).ok()?).ok()?;
+
+    let encoded = proto_seed::<T, P>(&value);
+    idempotent("protobuf encoding", &encoded, |bytes| {
+        let message = never_errors_on_valid("decode", P::decode(&bytes[..]));
+        let decoded = T::try_from(message)
+            .ok()
+            .expect("re-encoded message failed to convert");
+        proto_seed::<T, P>(&decoded)
+    });
+    Some(value)
+}
+
+/// `hash()` must be deterministic and survive both a binary and a protobuf round-trip unchanged
+fn check_hash_stability<T, P>(value: T, input_len: usize)
+where
+    T: Hashable + MessageFormat + Clone + TryFrom<P>,
+    P: Message + Default + From<T>,
+{
+    let hash = value.hash();
+    assert_eq!(value.hash(), hash, "hash is not deterministic");
+
+    let encoded = binary_seed(&value);
+    bounded_allocation(
+        "binary re-encoding",
+        input_len,
+        encoded.len(),
+        MAX_ENCODING_GROWTH,
+        ENCODING_SLACK,
+    );
+    let decoded = never_errors_on_valid("from_binary", T::from_binary(&encoded));
+    assert_eq!(
+        decoded.hash(),
+        hash,
+        "hash changed across a binary round-trip"
+    );
+
+    let encoded = proto_seed::<T, P>(&value);
+    bounded_allocation(
+        "protobuf re-encoding",
+        input_len,
+        encoded.len(),
+        MAX_ENCODING_GROWTH,
+        ENCODING_SLACK,
+    );
+    let message = never_errors_on_valid("decode", P::decode(&encoded[..]));
+    let decoded = T::try_from(message)
+        .ok()
+        .expect("re-encoded message failed to convert");
+    assert_eq!(
+        decoded.hash(),
+        hash,
+        "hash changed across a protobuf round-trip"
+    );
+}
+
+/// `encoding_idempotent`, and the decoded value must hash the same across re-encodings
+pub fn encoding_hash_binary<T, P>(data: &[u8])
+where
+    T: Hashable + MessageFormat + Clone + TryFrom<P>,
+    P: Message + Default + From<T>,
+{
+    if let Some(value) = encoding_idempotent::<T>(data) {
+        check_hash_stability::<T, P>(value, data.len());
+    }
+}
+
+/// `encoding_proto_idempotent`, and the decoded value must hash the same across re-encodings
+pub fn encoding_hash_proto<T, P>(data: &[u8])
+where
+    T: Hashable + MessageFormat + Clone + TryFrom<P>,
+    P: Message + Default + From<T>,
+{
+    if let Some(value) = encoding_proto_idempotent::<T, P>(data) {
+        check_hash_stability::<T, P>(value, data.len());
+    }
+}
+
+/// The binary encoding of a seed value
+pub fn binary_seed<T: MessageFormat>(value: &T) -> Vec<u8> {
+    value.to_binary().unwrap()
+}
+
+/// The protobuf encoding of a seed value, as the message `P`
+pub fn proto_seed<T: Clone, P: Message + From<T>>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    P::from(value.clone()).encode(&mut buf).unwrap();
+    buf
+}
+
+/// Registers encoding targets in two groups. `targets` takes a line per target: its name, the
+/// harness it runs and a seed, a function from a number to a valid input for the target. `types`
+/// takes a line per type with a binary encoding: the names of its decode and idempotence
+/// targets, the type and a function from a number to a valid value, which seeds both.
+macro_rules! encoding_targets {
+    (
+        targets { $($name:ident => $harness:expr, $seed:expr;)* }
+        types { $($dec:ident, $enc:ident => $ty:ty, $value:expr;)* }
+    ) => {
+        register_targets! {
+            ENCODING_TARGETS, ENCODING_TARGET_FNS;
+            $($name => $harness;)*
+            $(
+                $dec => encoding_decode::<$ty>;
+                $enc => encoding_idempotent::<$ty>;
+            )*
+        }
+
+        /// A valid input for `target`, derived from `n`
+        pub fn encoding_seed(target: &str, n: u64) -> Option<Vec<u8>> {
+            $(
+                if target == stringify!($name) {
+                    let seed: fn(u64) -> Vec<u8> = $seed;
+                    return Some(seed(n));
+                }
+            )*
+            $(
+                if target == stringify!($dec) || target == stringify!($enc) {
+                    let value: fn(u64) -> $ty = $value;
+                    return Some(binary_seed(&value(n)));
+                }
+            )*
+            None
+        }
+    };
+}
+
+encoding_targets! {
+    targets {
+        // A binary and a protobuf target per transaction type, seeded with parts of a valid
+        // transaction
+        core_trx_bin_transaction => encoding_idempotent::<Transaction>,
+            |n| binary_seed(&seed_transaction(n));
+        core_trx_proto_transaction =>
+            encoding_proto_idempotent::<Transaction, trx_proto::Transaction>,
+            |n| proto_seed::<_, trx_proto::Transaction>(&seed_transaction(n));
+        core_trx_bin_input => encoding_idempotent::<TransactionInput>,
+            |n| binary_seed(&seed_transaction(n).body.inputs()[0]);
+        core_trx_proto_input =>
+            encoding_proto_idempotent::<TransactionInput, trx_proto::TransactionInput>,
+            |n| proto_seed::<_, trx_proto::TransactionInput>(
+                &seed_transaction(n).body.inputs()[0],
+            );
+        core_trx_bin_output => encoding_idempotent::<TransactionOutput>,
+            |n| binary_seed(&seed_transaction(n).body.outputs()[0]);
+        core_trx_proto_output =>
+            encoding_proto_idempotent::<TransactionOutput, trx_proto::TransactionOutput>,
+            |n| proto_seed::<_, trx_proto::TransactionOutput>(
+                &seed_transaction(n).body.outputs()[0],
+            );
+        core_trx_bin_kernel => encoding_idempotent::<TransactionKernel>,
+            |n| binary_seed(&seed_transaction(n).body.kernels()[0]);
+        core_trx_proto_kernel =>
+            encoding_proto_idempotent::<TransactionKernel, trx_proto::TransactionKernel>,
+            |n| proto_seed::<_, trx_proto::TransactionKernel>(
+                &seed_transaction(n).body.kernels()[0],
+            );
+        core_trx_bin_body => encoding_idempotent::<AggregateBody>,
+            |n| binary_seed(&seed_transaction(n).body);
+        core_trx_proto_body =>
+            encoding_proto_idempotent::<AggregateBody, trx_proto::AggregateBody>,
+            |n| proto_seed::<_, trx_proto::AggregateBody>(&seed_transaction(n).body);
+
+        // A binary and a protobuf target per block type, which also check hashing, seeded with
+        // blocks up to 15 past the genesis block
+        core_block_bin_header => encoding_hash_binary::<BlockHeader, block_proto::BlockHeader>,
+            |n| binary_seed(&seed_block(n % 16).header);
+        core_block_proto_header => encoding_hash_proto::<BlockHeader, block_proto::BlockHeader>,
+            |n| proto_seed::<_, block_proto::BlockHeader>(&seed_block(n % 16).header);
+        core_block_bin_block => encoding_hash_binary::<Block, block_proto::Block>,
+            |n| binary_seed(&seed_block(n % 16));
+        core_block_proto_block => encoding_hash_proto::<Block, block_proto::Block>,
+            |n| proto_seed::<_, block_proto::Block>(&seed_block(n % 16));
+    }
+    types {
+        core_dec_output_features, core_enc_output_features => OutputFeatures,
+            |n| OutputFeatures::with_maturity(n);
+        core_dec_kernel_features, core_enc_kernel_features => KernelFeatures,
+            |_| KernelFeatures::empty();
+        core_dec_commitment, core_enc_commitment => Commitment,
+            |n| seed_transaction(n).body.outputs()[0].commitment.clone();
+        core_dec_public_key, core_enc_public_key => PublicKey, |_| PublicKey::default();
+        core_dec_signature, core_enc_signature => Signature,
+            |n| seed_transaction(n).body.kernels()[0].excess_sig.clone();
+        core_dec_proof_of_work, core_enc_proof_of_work => ProofOfWork, |_| ProofOfWork::default();
+        core_dec_block_header, core_enc_block_header => BlockHeader, |n| seed_block(n % 16).header;
+    }
+}
@@ -1,26 +1,283 @@
-use rand::rngs::OsRng;
+use common::{commutative, matches_checked, round_trip, DataReader};
+use prost::Message;
+use std::{convert::TryFrom, str::FromStr};
 use tari_core::transactions::{
-            fee::Fee,
-            helpers::{create_random_signature_from_s_key, create_utxo, spend_utxos},
-            tari_amount::uT,
-            transaction::{KernelBuilder, KernelFeatures, OutputFeatures, UnblindedOutput},
-            transaction_protocol::{proto, recipient::RecipientSignedMessage, sender::TransactionSenderMessage}, 
-            types::{CryptoFactories, HashDigest, PrivateKey, PublicKey},
- };
+    fee::Fee,
+    tari_amount::MicroTari,
+    transaction::{KernelFeatures, OutputFeatures, UnblindedOutput},
+    transaction_protocol::{
+        proto,
+        receiver::ReceiverTransactionProtocol,
+        recipient::RecipientSignedMessage,
+        sender::{SenderTransactionProtocol, TransactionSenderMessage},
+    },
+    types::{CryptoFactories, HashDigest, PrivateKey},
+};
 
+/// Where the sender protocol should be, according to the calls that succeeded so far
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SenderStage {
+    MessageReady,
+    CollectingSignature,
+    Finalizing,
+    Finalized,
+}
 
+/// A deterministic key, so crashes reproduce
+fn fuzzed_key(reader: &mut DataReader) -> PrivateKey {
+    PrivateKey::from(reader.read_u64().wrapping_add(1))
+}
 
-// accept string and return &mut Self
-pub fn core_trx_with_message(data: &[u8]) {
+/// Drive `SenderTransactionProtocol` through its builder and states.
+///
+/// The input sets the amounts, fee, lock height and message, then picks a sequence of
+/// transitions: building and fetching the single round message, adding an honest or a fuzzed
+/// `RecipientSignedMessage` and finalizing. Transitions that are invalid for the current state
+/// must be rejected, and a finalized transaction must be valid. Fuzzed `TransactionSenderMessage`s
+/// are fed to the receiver side as well.
+pub fn core_trx_sender_protocol(data: &[u8]) {
     let factories = CryptoFactories::default();
+    let mut reader = DataReader::new(data);
+
+    let input_value = MicroTari::from(reader.read_u32() as u64);
+    let amount = MicroTari::from(reader.read_u32() as u64);
+    let fee_per_gram = MicroTari::from(reader.read_u16() as u64);
+    let lock_height = reader.read_u64();
+    let message = reader.read_string(64);
+    let input = UnblindedOutput::new(input_value, fuzzed_key(&mut reader), None);
+
     let mut builder = SenderTransactionProtocol::builder(1);
-    let amount = MicroTari::from(10_000);
-    let input = UnblindedOutput::new(MicroTari::from(100_000), PrivateKey::random(&mut OsRng), None);
-    builder.with_lock_height(0).with_fee_per_gram(MicroTari::from(177)).with_offset(PrivateKey::random(&mut OsRng)).with_private_nonce(PrivateKey::random(&mut OsRng)).with_amount(0, amount).with_message("Yo!".to_string()).with_input(input.as_transaction_input(&factories.commitment, OutputFeatures::default()),input.clone(),).with_change_secret(PrivateKey::random(&mut OsRng));
+    builder
+        .with_lock_height(lock_height)
+        .with_fee_per_gram(fee_per_gram)
+        .with_offset(fuzzed_key(&mut reader))
+        .with_private_nonce(fuzzed_key(&mut reader))
+        .with_amount(0, amount)
+        .with_message(message)
+        .with_input(
+            input.as_transaction_input(&factories.commitment, OutputFeatures::default()),
+            input.clone(),
+        )
+        .with_change_secret(fuzzed_key(&mut reader));
+    let mut stp = match builder.build::<HashDigest>(&factories) {
+        Ok(stp) => stp,
+        Err(_) => return,
+    };
+    let mut stage = SenderStage::MessageReady;
+
+    while !reader.is_empty() && !stp.is_failed() {
+        match reader.read_u8() % 6 {
+            0 => {
+                let result = stp.build_single_round_message();
+                assert_eq!(result.is_ok(), stage == SenderStage::MessageReady);
+                if result.is_ok() {
+                    stage = SenderStage::CollectingSignature;
+                }
+            }
+            1 => {
+                let result = stp.get_single_round_message();
+                let valid =
+                    stage == SenderStage::MessageReady || stage == SenderStage::CollectingSignature;
+                assert_eq!(result.is_ok(), valid);
+            }
+            2 => {
+                // An honest reply from a receiver
+                let msg = match stp.get_single_round_message() {
+                    Ok(msg) => msg,
+                    Err(_) => continue,
+                };
+                let rtp = ReceiverTransactionProtocol::new(
+                    TransactionSenderMessage::Single(Box::new(msg)),
+                    fuzzed_key(&mut reader),
+                    fuzzed_key(&mut reader),
+                    OutputFeatures::default(),
+                    &factories,
+                );
+                let reply = rtp.get_signed_data().unwrap().clone();
+                let result = stp.add_single_recipient_info(reply, &factories.range_proof);
+                if result.is_ok() {
+                    assert_eq!(stage, SenderStage::CollectingSignature);
+                    stage = SenderStage::Finalizing;
+                } else {
+                    assert_ne!(
+                        stage,
+                        SenderStage::CollectingSignature,
+                        "honest reply rejected"
+                    );
+                }
+            }
+            3 => {
+                // A reply decoded from the input
+                let bytes = reader.read_vec(1024);
+                let reply = match proto::RecipientSignedMessage::decode(&bytes[..])
+                    .ok()
+                    .and_then(|msg| RecipientSignedMessage::try_from(msg).ok())
+                {
+                    Some(reply) => reply,
+                    None => continue,
+                };
+                if stp
+                    .add_single_recipient_info(reply, &factories.range_proof)
+                    .is_ok()
+                {
+                    assert_eq!(stage, SenderStage::CollectingSignature);
+                    stage = SenderStage::Finalizing;
+                }
+            }
+            4 => {
+                let result = stp.finalize(KernelFeatures::empty(), &factories);
+                if result.is_ok() {
+                    assert_eq!(stage, SenderStage::Finalizing);
+                    assert!(stp.is_finalized());
+                    let tx = stp.get_transaction().unwrap();
+                    assert!(
+                        tx.validate_internal_consistency(&factories, None).is_ok(),
+                        "finalized an invalid transaction"
+                    );
+                    stage = SenderStage::Finalized;
+                }
+            }
+            _ => {
+                // A sender message decoded from the input, handed to the receiver side
+                let bytes = reader.read_vec(1024);
+                if let Some(msg) = proto::TransactionSenderMessage::decode(&bytes[..])
+                    .ok()
+                    .and_then(|msg| TransactionSenderMessage::try_from(msg).ok())
+                {
+                    let rtp = ReceiverTransactionProtocol::new(
+                        msg,
+                        fuzzed_key(&mut reader),
+                        fuzzed_key(&mut reader),
+                        OutputFeatures::default(),
+                        &factories,
+                    );
+                    let _ = rtp.get_signed_data();
+                }
+            }
+        }
+    }
+
+    if stp.is_failed() {
+        assert!(stp.build_single_round_message().is_err());
+        assert!(stp.finalize(KernelFeatures::empty(), &factories).is_err());
+    }
+}
+
+/// `Fee::calculate` for any fee rate and transaction size.
+///
+/// The fee is the fee per gram times the weight of the transaction, and every kernel, input and
+/// output adds a fixed weight, read off single-item transactions. The fee of full-width counts
+/// and rates must match that model, wrapping like `MicroTari` arithmetic where it overflows and
+/// never panicking. Wherever the fee fits, raising any argument must not lower it.
+pub fn core_fee_calculate(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let args = [
+        reader.read_u64(),
+        reader.read_u64(),
+        reader.read_u64(),
+        reader.read_u64(),
+    ];
+    let bump = 1 + reader.read_u32() as u64;
+
+    let calculate = |[fee_per_gram, kernels, inputs, outputs]: [u64; 4]| {
+        u64::from(Fee::calculate(
+            MicroTari::from(fee_per_gram),
+            kernels as usize,
+            inputs as usize,
+            outputs as usize,
+        ))
+    };
+    let weights = [
+        calculate([1, 1, 0, 0]),
+        calculate([1, 0, 1, 0]),
+        calculate([1, 0, 0, 1]),
+    ];
+    assert!(
+        weights.iter().all(|&weight| weight > 0),
+        "a kernel, input or output weighs nothing: {:?}",
+        weights
+    );
+    assert_eq!(calculate([args[0], 0, 0, 0]), 0);
+
+    let checked_fee = |args: [u64; 4]| {
+        weights
+            .iter()
+            .zip(&args[1..])
+            .try_fold(0u64, |weight, (w, n)| {
+                weight.checked_add(w.checked_mul(*n)?)
+            })
+            .and_then(|weight| weight.checked_mul(args[0]))
+    };
+    let wrapping_fee = weights
+        .iter()
+        .zip(&args[1..])
+        .fold(0u64, |weight, (w, n)| {
+            weight.wrapping_add(w.wrapping_mul(*n))
+        })
+        .wrapping_mul(args[0]);
+    let fee = calculate(args);
+    matches_checked("Fee::calculate", checked_fee(args), wrapping_fee, || fee);
+
+    for (i, arg) in args.iter().enumerate() {
+        let mut bumped = args;
+        bumped[i] = match arg.checked_add(bump) {
+            Some(arg) => arg,
+            None => continue,
+        };
+        if checked_fee(bumped).is_some() {
+            assert!(
+                calculate(bumped) >= fee,
+                "raising argument {} of Fee::calculate{:?} by {} lowered the fee",
+                i,
+                args,
+                bump
+            );
+        }
+    }
+}
 
-    //if let Ok(s) = std::str::from_utf8(data) {
-      //  with_message(s);
+/// `MicroTari` arithmetic must agree with `u64` wherever the result fits and wrap without
+/// panicking everywhere else, and parsing the displayed value must give it back
+pub fn core_micro_tari_arith(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let a = reader.read_u64();
+    let b = reader.read_u64();
+    let (ma, mb) = (MicroTari::from(a), MicroTari::from(b));
 
+    matches_checked(
+        "MicroTari + MicroTari",
+        a.checked_add(b),
+        a.wrapping_add(b),
+        || commutative("MicroTari + MicroTari", &ma, &mb, |x, y| u64::from(*x + *y)),
+    );
+    matches_checked(
+        "MicroTari - MicroTari",
+        a.checked_sub(b),
+        a.wrapping_sub(b),
+        || u64::from(ma - mb),
+    );
+    matches_checked(
+        "MicroTari * u64",
+        a.checked_mul(b),
+        a.wrapping_mul(b),
+        || u64::from(ma * b),
+    );
+    assert_eq!(ma < mb, a < b);
 
-    //}
+    round_trip(
+        "MicroTari Display -> FromStr",
+        &ma,
+        |m| m.to_string(),
+        |s| MicroTari::from_str(&s).ok(),
+    );
+    if let Ok(s) = std::str::from_utf8(reader.rest()) {
+        if let Ok(parsed) = MicroTari::from_str(s) {
+            round_trip(
+                "parsed MicroTari Display -> FromStr",
+                &parsed,
+                |m| m.to_string(),
+                |s| MicroTari::from_str(&s).ok(),
+            );
+        }
+    }
 }
@@ -1,10 +1,16 @@
+use common::{round_trip, DataReader};
 use digest::Digest;
+use std::collections::BTreeSet;
 use tari_crypto::common::Blake256;
-use tari_mmr::MerkleMountainRange;
+use tari_mmr::{
+    functions::{calculate_mmr_root, calculate_pruned_mmr_root, prune_mmr, prune_mutable_mmr},
+    Bitmap, Hash, MerkleChangeTracker, MerkleChangeTrackerConfig, MerkleMountainRange, MerkleProof,
+    MutableMmr, MutableMmrLeafNodes,
+};
+use tari_utilities::message_format::MessageFormat;
 
 pub type Hasher = Blake256;
 
-
 pub fn mmr_push_bytes(data: &[u8]) {
     if let Ok(_s) = std::str::from_utf8(data) {
         let mut mmr = MerkleMountainRange::<Hasher, _>::new(Vec::default());
@@ -34,3 +40,418 @@ pub fn mmr_push_bytes(data: &[u8]) {
         // let pushd1 = mmr.push(&data.to_vec());
     }
 }
+
+/// Build an MMR with 1..=256 leaves taken from the input
+fn mmr_from_reader(reader: &mut DataReader) -> MerkleMountainRange<Hasher, Vec<Hash>> {
+    let mut mmr = MerkleMountainRange::<Hasher, _>::new(Vec::default());
+    let leaf_count = reader.read_u8() as usize + 1;
+    for _ in 0..leaf_count {
+        let leaf = Hasher::digest(reader.read_bytes(8)).to_vec();
+        assert!(mmr.push(leaf).is_ok());
+    }
+    mmr
+}
+
+/// Generate proofs for fuzzed leaf indices and verify them against the root
+pub fn mmr_proof_verify(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mmr = mmr_from_reader(&mut reader);
+    let leaf_count = mmr.get_leaf_count().unwrap();
+    let root = mmr.get_merkle_root().unwrap();
+
+    while !reader.is_empty() {
+        let leaf_pos = reader.read_u16() as usize;
+        match MerkleProof::for_leaf_node(&mmr, leaf_pos) {
+            Ok(proof) => {
+                assert!(leaf_pos < leaf_count, "proof created for a missing leaf");
+                let hash = mmr.get_leaf_hash(leaf_pos).unwrap().unwrap();
+                assert!(proof.verify_leaf::<Hasher>(&root, &hash, leaf_pos).is_ok());
+
+                let mut forged = hash.clone();
+                forged[0] ^= 1;
+                assert!(proof
+                    .verify_leaf::<Hasher>(&root, &forged, leaf_pos)
+                    .is_err());
+            }
+            Err(_) => assert!(leaf_pos >= leaf_count, "no proof for an existing leaf"),
+        }
+    }
+}
+
+/// Verify a `MerkleProof` decoded from the input against a real MMR.
+///
+/// Malformed proofs must be rejected without panicking, and a proof can only validate a hash
+/// that really is the leaf at the claimed position.
+pub fn mmr_proof_forged(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mmr = mmr_from_reader(&mut reader);
+    let root = mmr.get_merkle_root().unwrap();
+    let leaf_pos = reader.read_u16() as usize;
+    let hash = if reader.read_bool() {
+        mmr.get_leaf_hash(leaf_pos)
+            .ok()
+            .and_then(|h| h)
+            .unwrap_or_default()
+    } else {
+        reader.read_bytes(32).to_vec()
+    };
+
+    if let Ok(proof) = MerkleProof::from_binary(reader.rest()) {
+        if proof.verify_leaf::<Hasher>(&root, &hash, leaf_pos).is_ok() {
+            assert_eq!(
+                mmr.get_leaf_hash(leaf_pos).unwrap(),
+                Some(hash),
+                "forged proof validated"
+            );
+        }
+    }
+}
+
+/// Plain in-memory model of a `MutableMmr`: the leaf hashes and the set of deleted leaves
+#[derive(Clone, Default)]
+struct MmrModel {
+    leaves: Vec<Hash>,
+    deleted: BTreeSet<u32>,
+}
+
+impl MmrModel {
+    fn push(&mut self, hash: Hash) -> usize {
+        self.leaves.push(hash);
+        self.leaves.len() - 1
+    }
+
+    fn delete(&mut self, leaf_index: u32) -> bool {
+        (leaf_index as usize) < self.leaves.len() && self.deleted.insert(leaf_index)
+    }
+
+    /// Root of a `MutableMmr` built from scratch out of the model state
+    fn root(&self) -> Hash {
+        let mut mmr = MutableMmr::<Hasher, _>::new(Vec::default(), Bitmap::create()).unwrap();
+        for leaf in &self.leaves {
+            mmr.push(leaf.clone()).unwrap();
+        }
+        for &index in &self.deleted {
+            assert!(mmr.delete(index));
+        }
+        mmr.get_merkle_root().unwrap()
+    }
+
+    /// Check that `mmr` holds exactly the model state
+    fn check(&self, mmr: &MutableMmr<Hasher, Vec<Hash>>) {
+        assert_eq!(mmr.get_leaf_count(), self.leaves.len());
+        let deleted: Vec<u32> = mmr.deleted().iter().collect();
+        let expected: Vec<u32> = self.deleted.iter().cloned().collect();
+        assert_eq!(deleted, expected, "deleted leaf sets differ");
+        for (index, leaf) in self.leaves.iter().enumerate() {
+            let (hash, deleted) = mmr.get_leaf_status(index as u32).unwrap();
+            assert_eq!(hash.as_ref(), Some(leaf));
+            assert_eq!(deleted, self.deleted.contains(&(index as u32)));
+        }
+    }
+}
+
+/// Whether the MMR is compared against the model after `ops` operations. The model rebuilds an
+/// MMR to get its root, so checking after every operation would be quadratic in the input size;
+/// checking at powers of two and once at the end still catches a divergence soon after it happens.
+fn is_model_checkpoint(ops: usize) -> bool {
+    ops.is_power_of_two()
+}
+
+/// The next leaf hash to push, taken from the input
+fn next_leaf(reader: &mut DataReader) -> Hash {
+    Hasher::digest(reader.read_bytes(4)).to_vec()
+}
+
+/// The next leaf index to delete: mostly existing leaves, sometimes out of range ones
+fn next_leaf_index(reader: &mut DataReader, leaf_count: usize) -> u32 {
+    let index = reader.read_u16() as u32;
+    if reader.read_bool() || leaf_count == 0 {
+        index
+    } else {
+        index % leaf_count as u32
+    }
+}
+
+/// Compare a `MutableMmr` against the model. Compressing changes the bitmap encoding that feeds
+/// into the root, so full roots are only compared until the first compression.
+fn check_mutable_mmr(mmr: &MutableMmr<Hasher, Vec<Hash>>, model: &MmrModel, compressed: bool) {
+    model.check(mmr);
+    if !compressed {
+        assert_eq!(mmr.get_merkle_root().unwrap(), model.root());
+    }
+}
+
+/// Run push/delete/compress sequences against a `MutableMmr` and the in-memory model
+pub fn mmr_mutable_ops(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mut mmr = MutableMmr::<Hasher, _>::new(Vec::default(), Bitmap::create()).unwrap();
+    let mut model = MmrModel::default();
+    let mut compressed = false;
+    let mut ops = 0;
+
+    while !reader.is_empty() {
+        match reader.read_u8() % 3 {
+            0 => {
+                let leaf = next_leaf(&mut reader);
+                assert_eq!(mmr.push(leaf.clone()).unwrap(), model.push(leaf));
+            }
+            1 => {
+                let index = next_leaf_index(&mut reader, model.leaves.len());
+                assert_eq!(mmr.delete(index), model.delete(index));
+            }
+            _ => {
+                let mmr_only_root = mmr.get_mmr_only_root().unwrap();
+                mmr.compress();
+                compressed = true;
+                assert_eq!(mmr.get_mmr_only_root().unwrap(), mmr_only_root);
+            }
+        }
+        ops += 1;
+        if is_model_checkpoint(ops) {
+            check_mutable_mmr(&mmr, &model, compressed);
+        }
+    }
+    check_mutable_mmr(&mmr, &model, compressed);
+    assert!(mmr.validate().is_ok());
+}
+
+/// Run push/delete/commit/rewind/reset sequences against a `MerkleChangeTracker`.
+///
+/// Every commit records the model state and root, so resetting or rewinding must restore a
+/// recorded root exactly.
+pub fn mmr_change_tracker_ops(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let base = MutableMmr::<Hasher, _>::new(Vec::default(), Bitmap::create()).unwrap();
+    let config = MerkleChangeTrackerConfig {
+        min_history_len: usize::MAX / 2,
+        max_history_len: usize::MAX,
+    };
+    let mut tracker = MerkleChangeTracker::new(base, Vec::new(), config).unwrap();
+    let mut model = MmrModel::default();
+    // Committed states, starting with the empty MMR
+    let mut history = vec![(model.clone(), tracker.get_merkle_root().unwrap())];
+    let mut ops = 0;
+
+    while !reader.is_empty() {
+        match reader.read_u8() % 5 {
+            0 => {
+                let leaf = next_leaf(&mut reader);
+                assert_eq!(tracker.push(leaf.clone()).unwrap(), model.push(leaf));
+            }
+            1 => {
+                let index = next_leaf_index(&mut reader, model.leaves.len());
+                assert_eq!(tracker.delete(index), model.delete(index));
+            }
+            2 => {
+                tracker.commit().unwrap();
+                history.push((model.clone(), tracker.get_merkle_root().unwrap()));
+            }
+            3 => {
+                let steps_back = reader.read_u8() as usize;
+                let checkpoints = history.len() - 1;
+                if tracker.rewind(steps_back).is_ok() {
+                    assert!(
+                        steps_back <= checkpoints,
+                        "rewound past the first checkpoint"
+                    );
+                    history.truncate(history.len() - steps_back);
+                    let (state, root) = history.last().unwrap();
+                    model = state.clone();
+                    assert_eq!(
+                        &tracker.get_merkle_root().unwrap(),
+                        root,
+                        "rewind did not restore root"
+                    );
+                } else {
+                    assert!(
+                        steps_back > checkpoints,
+                        "rewind to an existing checkpoint failed"
+                    );
+                }
+            }
+            _ => {
+                tracker.reset().unwrap();
+                let (state, root) = history.last().unwrap();
+                model = state.clone();
+                assert_eq!(
+                    &tracker.get_merkle_root().unwrap(),
+                    root,
+                    "reset did not restore root"
+                );
+            }
+        }
+        assert_eq!(tracker.checkpoint_count().unwrap(), history.len() - 1);
+        assert_eq!(tracker.get_leaf_count(), model.leaves.len());
+        ops += 1;
+        if is_model_checkpoint(ops) {
+            assert_eq!(tracker.get_merkle_root().unwrap(), model.root());
+        }
+    }
+    assert_eq!(tracker.get_merkle_root().unwrap(), model.root());
+}
+
+/// Pruned MMRs and the root calculation helpers must agree with the full MMR they came from
+pub fn mmr_pruned_root(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mut mmr = mmr_from_reader(&mut reader);
+    let mut pruned = prune_mmr(&mmr).unwrap();
+    assert_eq!(pruned.get_merkle_root(), mmr.get_merkle_root());
+    assert_eq!(pruned.get_leaf_count(), mmr.get_leaf_count());
+
+    let additions: Vec<Hash> = (0..reader.read_u8())
+        .map(|_| next_leaf(&mut reader))
+        .collect();
+    let calculated = calculate_mmr_root(&mmr, additions.clone()).unwrap();
+    for leaf in additions {
+        mmr.push(leaf.clone()).unwrap();
+        pruned.push(leaf).unwrap();
+    }
+    assert_eq!(mmr.get_merkle_root().unwrap(), calculated);
+    assert_eq!(pruned.get_merkle_root().unwrap(), calculated);
+}
+
+/// Same as `mmr_pruned_root` for a `MutableMmr` with deletions
+pub fn mmr_pruned_mutable_root(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mut mmr = MutableMmr::<Hasher, _>::new(Vec::default(), Bitmap::create()).unwrap();
+    for _ in 0..=reader.read_u8() {
+        mmr.push(next_leaf(&mut reader)).unwrap();
+    }
+    let pruned = prune_mutable_mmr(&mmr).unwrap();
+    assert_eq!(pruned.get_merkle_root(), mmr.get_merkle_root());
+
+    let additions: Vec<Hash> = (0..reader.read_u8())
+        .map(|_| next_leaf(&mut reader))
+        .collect();
+    for leaf in &additions {
+        mmr.push(leaf.clone()).unwrap();
+    }
+    let mut deletions = Vec::new();
+    while !reader.is_empty() {
+        let index = next_leaf_index(&mut reader, mmr.get_leaf_count());
+        if mmr.delete(index) {
+            deletions.push(index);
+        }
+    }
+    let mut src = MutableMmr::<Hasher, _>::new(Vec::default(), Bitmap::create()).unwrap();
+    let base_leaves = mmr.get_leaf_count() - additions.len();
+    for index in 0..base_leaves {
+        src.push(mmr.get_leaf_status(index as u32).unwrap().0.unwrap())
+            .unwrap();
+    }
+    let calculated = calculate_pruned_mmr_root(&src, additions, deletions).unwrap();
+    assert_eq!(mmr.get_merkle_root().unwrap(), calculated);
+}
+
+/// `get_leaf_hashes` and `assign` with fuzzed ranges.
+///
+/// Ranges running past the end must be truncated or rejected, never panic, and assigning the
+/// returned hashes must give the same root as pushing them one by one.
+pub fn mmr_leaf_hashes_range(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mmr = mmr_from_reader(&mut reader);
+    let leaf_count = mmr.get_leaf_count().unwrap();
+
+    let all = mmr.get_leaf_hashes(0, leaf_count).unwrap();
+    assert_eq!(all.len(), leaf_count);
+    let mut assigned = MerkleMountainRange::<Hasher, _>::new(Vec::default());
+    assigned.assign(all).unwrap();
+    assert_eq!(assigned.get_merkle_root(), mmr.get_merkle_root());
+
+    while !reader.is_empty() {
+        let index = reader.read_u64() as usize;
+        let count = reader.read_u64() as usize;
+        let hashes = match mmr.get_leaf_hashes(index, count) {
+            Ok(hashes) => hashes,
+            Err(_) => continue,
+        };
+        assert!(hashes.len() <= count.max(1));
+        for (offset, hash) in hashes.iter().enumerate() {
+            assert_eq!(
+                mmr.get_leaf_hash(index + offset).unwrap().as_ref(),
+                Some(hash)
+            );
+        }
+
+        let mut pushed = MerkleMountainRange::<Hasher, _>::new(Vec::default());
+        for hash in &hashes {
+            pushed.push(hash.clone()).unwrap();
+        }
+        let mut assigned = MerkleMountainRange::<Hasher, _>::new(Vec::default());
+        assigned.assign(hashes).unwrap();
+        assert_eq!(assigned.get_merkle_root(), pushed.get_merkle_root());
+    }
+}
+
+/// Check that every leaf of `mmr` reports the deletion status recorded in `deleted`
+fn check_deleted(mmr: &MutableMmr<Hasher, Vec<Hash>>, deleted: &Bitmap) {
+    for index in 0..mmr.get_leaf_count() as u32 {
+        let (_, is_deleted) = mmr.get_leaf_status(index).unwrap();
+        assert_eq!(is_deleted, deleted.contains(index));
+    }
+}
+
+/// Deserialize a deleted-leaf bitmap from the input and apply it to an MMR.
+///
+/// The input is a leaf count followed by a serialized roaring bitmap, fuzzed through the checked
+/// `Bitmap::try_deserialize` only. `Bitmap::deserialize`, the path `tari_mmr` uses when syncing,
+/// trusts its input and fails on malformed bytes by design, so raw input would only report that;
+/// it runs on the bytes the checked decoder accepted instead and must give the same bitmap.
+pub fn mmr_bitmap_deserialize(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let leaf_count = reader.read_u16();
+    let bytes = reader.rest();
+    let bitmap = match Bitmap::try_deserialize(bytes) {
+        Some(bitmap) => bitmap,
+        None => return,
+    };
+    assert_eq!(
+        Bitmap::deserialize(bytes),
+        bitmap,
+        "checked and unchecked decoding differ"
+    );
+    assert_eq!(bitmap.cardinality(), bitmap.iter().count() as u64);
+    round_trip(
+        "Bitmap serialize",
+        &bitmap,
+        |b| b.serialize(),
+        |s| Bitmap::try_deserialize(&s),
+    );
+
+    let mut mmr = MutableMmr::<Hasher, _>::new(Vec::default(), bitmap.clone()).unwrap();
+    for index in 0..leaf_count {
+        mmr.push(Hasher::digest(&index.to_le_bytes()).to_vec())
+            .unwrap();
+    }
+    check_deleted(&mmr, &bitmap);
+    assert_eq!(mmr.deleted(), &bitmap);
+    assert!(mmr.get_merkle_root().is_ok());
+
+    mmr.compress();
+    assert_eq!(mmr.deleted().cardinality(), bitmap.cardinality());
+    check_deleted(&mmr, &bitmap);
+    assert!(mmr.get_merkle_root().is_ok());
+}
+
+/// Decode `MutableMmrLeafNodes` from the input and assign them to an MMR, the way a syncing node
+/// restores the MMR state it receives from a peer
+pub fn mmr_leaf_nodes_deserialize(data: &[u8]) {
+    let nodes = match MutableMmrLeafNodes::from_binary(data) {
+        Ok(nodes) => nodes,
+        Err(_) => return,
+    };
+    let leaf_count = nodes.leaf_hashes.len();
+    let deleted = nodes.deleted.clone();
+    let encoded = nodes.to_binary().unwrap();
+
+    let mut mmr = MutableMmr::<Hasher, _>::new(Vec::default(), Bitmap::create()).unwrap();
+    if mmr.assign(nodes).is_err() {
+        return;
+    }
+    assert_eq!(mmr.get_leaf_count(), leaf_count);
+    check_deleted(&mmr, &deleted);
+    assert!(mmr.get_merkle_root().is_ok());
+
+    let restored = mmr.to_leaf_nodes(0, leaf_count).unwrap();
+    assert_eq!(restored.to_binary().unwrap(), encoded);
+}
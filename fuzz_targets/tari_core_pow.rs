@@ -0,0 +1,162 @@
+use common::{commutative, matches_checked, DataReader};
+use tari_core::{
+    blocks::BlockHeader,
+    proof_of_work::{
+        lwma_diff::LinearWeightedMovingAverage, monero_difficulty, sha3_difficulty, Difficulty,
+        DifficultyAdjustment, PowAlgorithm,
+    },
+};
+use tari_crypto::tari_utilities::epoch_time::EpochTime;
+
+/// `Difficulty` arithmetic must agree with plain `u64` arithmetic wherever the result fits, and
+/// saturate without panicking everywhere else: accumulated difficulty that wrapped would make
+/// the chain with the most work look like the one with the least
+pub fn core_pow_difficulty_arith(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let a = reader.read_u64();
+    let b = reader.read_u64();
+    let (da, db) = (Difficulty::from(a), Difficulty::from(b));
+
+    assert_eq!(da.as_u64(), a);
+    assert_eq!(da < db, a < b);
+    assert_eq!(da == db, a == b);
+    assert!(Difficulty::min().as_u64() <= 1);
+    matches_checked(
+        "Difficulty + Difficulty",
+        a.checked_add(b),
+        a.saturating_add(b),
+        || {
+            commutative("Difficulty + Difficulty", &da, &db, |x, y| {
+                (*x + *y).as_u64()
+            })
+        },
+    );
+    matches_checked(
+        "Difficulty += Difficulty",
+        a.checked_add(b),
+        a.saturating_add(b),
+        || {
+            let mut acc = da;
+            acc += db;
+            acc.as_u64()
+        },
+    );
+    matches_checked(
+        "Difficulty - Difficulty",
+        a.checked_sub(b),
+        a.saturating_sub(b),
+        || (da - db).as_u64(),
+    );
+}
+
+/// A window of `(timestamp, difficulty)` pairs read from the input
+fn read_window(reader: &mut DataReader) -> Vec<(u64, u64)> {
+    let mut timestamp = reader.read_u32() as u64;
+    let mut window = Vec::new();
+    while !reader.is_empty() && window.len() < 256 {
+        // Mostly increasing timestamps, with the occasional step backwards
+        let step = reader.read_u16() as i64 - 0x1000;
+        timestamp = (timestamp as i64 + step).max(0) as u64;
+        window.push((timestamp, 1 + reader.read_u32() as u64));
+    }
+    window
+}
+
+fn lwma_difficulty(config: (usize, u64, u64, u64), window: &[(u64, u64)]) -> Option<u64> {
+    let (block_window, target_time, initial_difficulty, max_block_time) = config;
+    let mut lwma = LinearWeightedMovingAverage::new(
+        block_window,
+        target_time,
+        initial_difficulty,
+        max_block_time,
+    );
+    for &(timestamp, difficulty) in window {
+        if lwma
+            .add(EpochTime::from(timestamp), Difficulty::from(difficulty))
+            .is_err()
+        {
+            return None;
+        }
+    }
+    Some(lwma.get_difficulty().as_u64())
+}
+
+/// Feed fuzzed timestamp and difficulty windows to the LWMA difficulty adjustment.
+///
+/// It must never panic on overflow, must stay above the minimum difficulty and below what the
+/// fastest possible blocks could justify, and must be monotone in the difficulties: raising
+/// any difficulty in the window can never lower the result.
+pub fn core_pow_lwma(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let block_window = 1 + reader.read_u8() as usize % 90;
+    let target_time = 1 + reader.read_u16() as u64;
+    let initial_difficulty = 1 + reader.read_u32() as u64;
+    let max_block_time = target_time * (1 + reader.read_u8() as u64 % 10);
+    let config = (
+        block_window,
+        target_time,
+        initial_difficulty,
+        max_block_time,
+    );
+    let bump = reader.read_u32() as u64;
+    let window = read_window(&mut reader);
+
+    let difficulty = match lwma_difficulty(config, &window) {
+        Some(difficulty) => difficulty,
+        None => return,
+    };
+    assert!(difficulty >= Difficulty::min().as_u64());
+    let max_in_window = window
+        .iter()
+        .rev()
+        .take(block_window + 1)
+        .map(|&(_, d)| d)
+        .chain(Some(initial_difficulty))
+        .max()
+        .unwrap();
+    assert!(
+        difficulty <= max_in_window.saturating_mul(target_time),
+        "difficulty {} out of bounds for window max {}",
+        difficulty,
+        max_in_window
+    );
+
+    let bumped: Vec<(u64, u64)> = window.iter().map(|&(t, d)| (t, d + bump)).collect();
+    if let Some(bumped_difficulty) = lwma_difficulty(config, &bumped) {
+        assert!(
+            bumped_difficulty >= difficulty,
+            "raising difficulties lowered the adjustment"
+        );
+    }
+}
+
+/// Achieved difficulty for SHA3 and Monero PoW data taken from the input. Neither may panic, and
+/// Monero data must either be rejected or give a difficulty of at least 1.
+pub fn core_pow_achieved_difficulty(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let mut header = BlockHeader::new(reader.read_u16());
+    header.height = reader.read_u64();
+    header.nonce = reader.read_u64();
+    header.timestamp = EpochTime::from(reader.read_u64());
+    header.pow.pow_algo = if reader.read_bool() {
+        PowAlgorithm::Sha3
+    } else {
+        PowAlgorithm::Monero
+    };
+    header.pow.pow_data = reader.rest().to_vec();
+
+    let sha3 = sha3_difficulty(&header);
+    assert_eq!(
+        sha3_difficulty(&header),
+        sha3,
+        "sha3 difficulty is not deterministic"
+    );
+    assert!(sha3 >= Difficulty::min());
+    if let Ok(monero) = monero_difficulty(&header) {
+        assert!(
+            monero.as_u64() >= 1,
+            "monero difficulty {} is below 1",
+            monero.as_u64()
+        );
+    }
+}
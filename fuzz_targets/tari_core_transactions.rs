@@ -1,28 +1,166 @@
+use common::{never_errors_on_valid, round_trip, DataReader};
 use tari_core::transactions::{
-    transaction::{OutputFeatures},
+    helpers::create_tx,
+    tari_amount::MicroTari,
+    transaction::{OutputFeatures, Transaction},
+    types::{Commitment, CryptoFactories},
 };
-
-
+use tari_utilities::message_format::MessageFormat;
 
 // accept u64
 pub fn core_trx_create_coinbase(data: &[u8]) {
     if let Ok(s) = std::str::from_utf8(data) {
         if let Ok(d) = u64::from_str_radix(s, 36) {
-            OutputFeatures::create_coinbase(d);
-
+            let features = OutputFeatures::create_coinbase(d);
+            assert_eq!(features.maturity, d);
+            output_features_round_trip("create_coinbase", &features);
         }
-
-
     }
 }
 // accept u64
 pub fn core_trx_with_maturity(data: &[u8]) {
     if let Ok(s) = std::str::from_utf8(data) {
         if let Ok(d) = u64::from_str_radix(s, 36) {
-            OutputFeatures::with_maturity(d);
-
+            let features = OutputFeatures::with_maturity(d);
+            assert_eq!(features.maturity, d);
+            output_features_round_trip("with_maturity", &features);
         }
+    }
+}
+
+/// Features built from any maturity must encode, and decode back to themselves
+fn output_features_round_trip(label: &str, features: &OutputFeatures) {
+    round_trip(
+        label,
+        features,
+        |f| never_errors_on_valid(label, f.to_binary()),
+        |bytes| OutputFeatures::from_binary(&bytes).ok(),
+    );
+}
+
+/// A valid transaction spending `amount`, the source of seeds for the encoding targets
+pub fn seed_transaction(amount: u64) -> Transaction {
+    let amount = MicroTari::from(1_000 + amount % 1_000_000);
+    create_tx(amount, MicroTari::from(25), 0, 2, 0, 2).0
+}
+
+/// Build a transaction from fuzzed parameters with `create_tx`, along with the values of every
+/// input and output it contains, keyed by commitment
+fn fuzzed_transaction(
+    reader: &mut DataReader,
+    factories: &CryptoFactories,
+) -> (Transaction, Vec<(Commitment, u64)>) {
+    let amount = MicroTari::from(100_000 + reader.read_u32() as u64);
+    let fee_per_gram = MicroTari::from(1 + reader.read_u8() as u64);
+    let lock_height = reader.read_u8() as u64;
+    let input_count = 1 + reader.read_u8() as usize % 4;
+    let output_count = 1 + reader.read_u8() as usize % 4;
+    let (tx, inputs, outputs) = create_tx(
+        amount,
+        fee_per_gram,
+        lock_height,
+        input_count,
+        0,
+        output_count,
+    );
+    let values = inputs
+        .iter()
+        .chain(outputs.iter())
+        .map(|utxo| {
+            let commitment = factories
+                .commitment
+                .commit_value(&utxo.spending_key, utxo.value.into());
+            (commitment, utxo.value.into())
+        })
+        .collect();
+    (tx, values)
+}
 
+/// Run the full validation path on a transaction assembled from fuzzed components.
+///
+/// Two valid transactions are generated, then the input decides which inputs, outputs and
+/// kernels to drop, duplicate or swap between them and how to tamper with kernel fees, lock
+/// heights and the offset. Validation must never panic, and any transaction it accepts must
+/// balance: sum(inputs) == sum(outputs) + fee.
+pub fn core_trx_validate(data: &[u8]) {
+    let factories = CryptoFactories::default();
+    let mut reader = DataReader::new(data);
+    let (tx, mut values) = fuzzed_transaction(&mut reader, &factories);
+    let (other, other_values) = fuzzed_transaction(&mut reader, &factories);
+    values.extend(other_values);
+
+    let mut inputs = tx.body.inputs().clone();
+    let mut outputs = tx.body.outputs().clone();
+    let mut kernels = tx.body.kernels().clone();
+    let mut offset = tx.offset.clone();
+
+    while !reader.is_empty() {
+        let op = reader.read_u8();
+        let index = reader.read_u8() as usize;
+        match op % 10 {
+            0 if !inputs.is_empty() => {
+                inputs.remove(index % inputs.len());
+            }
+            1 if !outputs.is_empty() => {
+                outputs.remove(index % outputs.len());
+            }
+            2 if !inputs.is_empty() => {
+                let input = inputs[index % inputs.len()].clone();
+                inputs.push(input);
+            }
+            3 if !outputs.is_empty() => {
+                let output = outputs[index % outputs.len()].clone();
+                outputs.push(output);
+            }
+            4 => {
+                let other_inputs = other.body.inputs();
+                inputs.push(other_inputs[index % other_inputs.len()].clone());
+            }
+            5 => {
+                let other_outputs = other.body.outputs();
+                outputs.push(other_outputs[index % other_outputs.len()].clone());
+            }
+            6 => kernels.push(other.body.kernels()[0].clone()),
+            7 if !kernels.is_empty() => {
+                let len = kernels.len();
+                kernels[index % len].fee = MicroTari::from(reader.read_u64());
+            }
+            8 if !kernels.is_empty() => {
+                let len = kernels.len();
+                kernels[index % len].lock_height = reader.read_u64();
+            }
+            9 => offset = other.offset.clone(),
+            _ => {}
+        }
+    }
 
+    let tx = Transaction::new(inputs, outputs, kernels, offset);
+    let _ = tx.calculate_ave_fee_per_gram();
+    if tx.validate_internal_consistency(&factories, None).is_ok() {
+        let value_of = |commitment: &Commitment| -> u128 {
+            values
+                .iter()
+                .find(|(c, _)| c == commitment)
+                .map(|(_, v)| *v as u128)
+                .expect("validated a commitment of unknown value")
+        };
+        let inputs: u128 = tx
+            .body
+            .inputs()
+            .iter()
+            .map(|i| value_of(&i.commitment))
+            .sum();
+        let outputs: u128 = tx
+            .body
+            .outputs()
+            .iter()
+            .map(|o| value_of(&o.commitment))
+            .sum();
+        let fee = u64::from(tx.body.get_total_fee()) as u128;
+        assert_eq!(
+            inputs,
+            outputs + fee,
+            "validated transaction does not balance"
+        );
     }
 }
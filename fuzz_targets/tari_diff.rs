@@ -0,0 +1,219 @@
+use std::fmt::Debug;
+use tari_core_blocks::seed_block;
+use tari_core_encoding::encoding_seed;
+use tari_utilities::message_format::MessageFormat;
+
+/// Names of the differential targets. They are listed without the `differential` feature so the
+/// CLI can tell the runner to enable it, but only build with it.
+pub const DIFF_TARGETS: &[&str] = &[
+    "diff_core_trx_decode",
+    "diff_core_trx_validate",
+    "diff_core_block_header_hash",
+    "diff_core_fee_calculate",
+    "diff_core_lwma",
+    "diff_mmr_root",
+];
+
+/// A valid encoding for the differential targets that decode one, `None` for the rest
+pub fn diff_seed(target: &str, amount: u64) -> Option<Vec<u8>> {
+    match target {
+        "diff_core_trx_decode" | "diff_core_trx_validate" => {
+            encoding_seed("core_trx_bin_transaction", amount)
+        }
+        "diff_core_block_header_hash" => seed_block(amount % 16).header.to_binary().ok(),
+        _ => None,
+    }
+}
+
+/// Reduce a result to something comparable across revisions: the canonical bytes of an `Ok`
+/// value or the full `Debug` output of an error, so nested variants and fields count too
+pub fn outcome<T, E: Debug, F: FnOnce(T) -> Vec<u8>>(
+    result: Result<T, E>,
+    canonical: F,
+) -> Result<Vec<u8>, String> {
+    match result {
+        Ok(value) => Ok(canonical(value)),
+        Err(err) => Err(format!("{:?}", err)),
+    }
+}
+
+/// Flag any difference between the current and the baseline implementation as a finding
+pub fn check_divergence<T: Debug + PartialEq>(what: &str, current: T, baseline: T, data: &[u8]) {
+    assert!(
+        current == baseline,
+        "divergence in {}: current {:?}, baseline {:?}, input {}",
+        what,
+        current,
+        baseline,
+        ::hex::encode(data)
+    );
+}
+
+#[cfg(feature = "differential")]
+pub use self::targets::*;
+
+/// The targets proper, each running the input through `tari_core` and `tari_core_baseline`
+#[cfg(feature = "differential")]
+mod targets {
+    use super::{check_divergence, outcome};
+    use common::DataReader;
+    use tari_core::{
+        blocks::BlockHeader,
+        proof_of_work::{lwma_diff::LinearWeightedMovingAverage, Difficulty, DifficultyAdjustment},
+        transactions::{
+            fee::Fee, tari_amount::MicroTari, transaction::Transaction, types::CryptoFactories,
+        },
+    };
+    use tari_core_baseline as baseline;
+    use tari_core_baseline::proof_of_work::DifficultyAdjustment as BaselineDifficultyAdjustment;
+    use tari_crypto::{
+        common::Blake256,
+        tari_utilities::{epoch_time::EpochTime, Hashable},
+    };
+    use tari_mmr::MerkleMountainRange;
+    use tari_mmr_baseline::MerkleMountainRange as BaselineMerkleMountainRange;
+    use tari_utilities::message_format::MessageFormat;
+
+    /// Functions of all differential targets
+    pub const DIFF_TARGET_FNS: &[(&str, ::TargetFn)] = target_fns![
+        diff_core_trx_decode,
+        diff_core_trx_validate,
+        diff_core_block_header_hash,
+        diff_core_fee_calculate,
+        diff_core_lwma,
+        diff_mmr_root,
+    ];
+
+    /// Decoding a transaction must succeed or fail alike and re-encode to the same bytes
+    pub fn diff_core_trx_decode(data: &[u8]) {
+        let current = outcome(Transaction::from_binary(data), |tx| tx.to_binary().unwrap());
+        let base = outcome(
+            baseline::transactions::transaction::Transaction::from_binary(data),
+            |tx| tx.to_binary().unwrap(),
+        );
+        check_divergence("transaction decoding", current, base, data);
+    }
+
+    /// A transaction both revisions decode must be accepted or rejected alike
+    pub fn diff_core_trx_validate(data: &[u8]) {
+        let (current, base) = match (
+            Transaction::from_binary(data),
+            baseline::transactions::transaction::Transaction::from_binary(data),
+        ) {
+            (Ok(current), Ok(base)) => (current, base),
+            _ => return,
+        };
+        let factories = CryptoFactories::default();
+        let base_factories = baseline::transactions::types::CryptoFactories::default();
+        check_divergence(
+            "transaction validation",
+            outcome(
+                current.validate_internal_consistency(&factories, None),
+                |_| Vec::new(),
+            ),
+            outcome(
+                base.validate_internal_consistency(&base_factories, None),
+                |_| Vec::new(),
+            ),
+            data,
+        );
+    }
+
+    /// Block header hashes are consensus critical and must not change between revisions
+    pub fn diff_core_block_header_hash(data: &[u8]) {
+        let current = outcome(BlockHeader::from_binary(data), |header| header.hash());
+        let base = outcome(baseline::blocks::BlockHeader::from_binary(data), |header| {
+            header.hash()
+        });
+        check_divergence("block header hash", current, base, data);
+    }
+
+    /// Fees must be calculated the same way by both revisions
+    pub fn diff_core_fee_calculate(data: &[u8]) {
+        let mut reader = DataReader::new(data);
+        let fee_per_gram = reader.read_u32() as u64;
+        let kernels = reader.read_u16() as usize;
+        let inputs = reader.read_u16() as usize;
+        let outputs = reader.read_u16() as usize;
+
+        let current = Fee::calculate(MicroTari::from(fee_per_gram), kernels, inputs, outputs);
+        let base = baseline::transactions::fee::Fee::calculate(
+            baseline::transactions::tari_amount::MicroTari::from(fee_per_gram),
+            kernels,
+            inputs,
+            outputs,
+        );
+        check_divergence("fee", u64::from(current), u64::from(base), data);
+    }
+
+    /// The LWMA difficulty adjustment must agree on every window, including rejected ones
+    pub fn diff_core_lwma(data: &[u8]) {
+        let mut reader = DataReader::new(data);
+        let block_window = 1 + reader.read_u8() as usize % 90;
+        let target_time = 1 + reader.read_u16() as u64;
+        let initial_difficulty = 1 + reader.read_u32() as u64;
+        let max_block_time = target_time * (1 + reader.read_u8() as u64 % 10);
+
+        let mut current = LinearWeightedMovingAverage::new(
+            block_window,
+            target_time,
+            initial_difficulty,
+            max_block_time,
+        );
+        let mut base = baseline::proof_of_work::lwma_diff::LinearWeightedMovingAverage::new(
+            block_window,
+            target_time,
+            initial_difficulty,
+            max_block_time,
+        );
+        while !reader.is_empty() {
+            let timestamp = reader.read_u64();
+            let difficulty = reader.read_u64();
+            check_divergence(
+                "LWMA window",
+                outcome(
+                    current.add(EpochTime::from(timestamp), Difficulty::from(difficulty)),
+                    |_| Vec::new(),
+                ),
+                outcome(
+                    base.add(
+                        EpochTime::from(timestamp),
+                        baseline::proof_of_work::Difficulty::from(difficulty),
+                    ),
+                    |_| Vec::new(),
+                ),
+                data,
+            );
+            check_divergence(
+                "LWMA difficulty",
+                current.get_difficulty().as_u64(),
+                base.get_difficulty().as_u64(),
+                data,
+            );
+        }
+    }
+
+    /// MMR roots are consensus critical; both revisions must agree on the root after every push
+    pub fn diff_mmr_root(data: &[u8]) {
+        let mut reader = DataReader::new(data);
+        let mut current = MerkleMountainRange::<Blake256, _>::new(Vec::new());
+        let mut base = BaselineMerkleMountainRange::<Blake256, _>::new(Vec::new());
+        while !reader.is_empty() {
+            let leaf = reader.read_vec(64);
+            check_divergence(
+                "MMR push",
+                outcome(current.push(leaf.clone()), |size| {
+                    size.to_le_bytes().to_vec()
+                }),
+                outcome(base.push(leaf), |size| size.to_le_bytes().to_vec()),
+                data,
+            );
+            check_divergence(
+                "MMR root",
+                outcome(current.get_merkle_root(), |root| root),
+                outcome(base.get_merkle_root(), |root| root),
+                data,
+            );
+        }
+    }
+}
@@ -1,25 +1,395 @@
 extern crate tari_utilities;
-use self::tari_utilities::{bit, hex};
+use self::tari_utilities::{bit, hex, message_format::MessageFormat, ExtendBytes};
+use common::{bounded_allocation, never_errors_on_valid, round_trip, DataReader};
+use tari_crypto::{
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::{hex::Hex, ByteArray},
+};
 
 //takes a &[T] and returns a String
 pub fn util_to_hex(data: &[u8]) {
-    hex::to_hex(data);
+    let s = hex::to_hex(data);
+    bounded_allocation("to_hex", data.len(), s.len(), 2, 0);
+    round_trip(
+        "to_hex",
+        &data.to_vec(),
+        |d| hex::to_hex(d),
+        |s| hex::from_hex(&s).ok(),
+    );
 }
 
 //takes in u8 and returns Vec<bool>
 pub fn util_bytes_to_bits(data: &[u8]) {
-    bit::bytes_to_bits(data);
+    let bits = bit::bytes_to_bits(data);
+    bounded_allocation("bytes_to_bits", data.len(), bits.len(), 8, 0);
+    round_trip(
+        "bytes_to_bits",
+        &data.to_vec(),
+        |d| bit::bytes_to_bits(d),
+        |bits| Some(bit::bits_to_bytes(&bits)),
+    );
 }
 
 //takes a &str and returns a Vec<u8>
 pub fn util_from_hex(data: &[u8]) {
     if let Ok(s) = std::str::from_utf8(data) {
-        let _ = hex::from_hex(&s);
+        if let Ok(bytes) = hex::from_hex(&s) {
+            bounded_allocation("from_hex", s.len(), bytes.len(), 1, 0);
+            round_trip(
+                "from_hex",
+                &bytes,
+                |b| hex::to_hex(b),
+                |s| hex::from_hex(&s).ok(),
+            );
+        }
     }
 }
 
 //takes &[Vec<u8>] and returns a Vec<String>
 pub fn util_to_hex_multiple(data: &[u8]) {
-    let s = [data.to_vec()];
-    hex::to_hex_multiple(&s);
+    round_trip(
+        "to_hex_multiple",
+        &data.to_vec(),
+        |d| hex::to_hex_multiple(&[d.clone()]),
+        |encoded| match &encoded[..] {
+            [s] => hex::from_hex(s).ok(),
+            _ => None,
+        },
+    );
+}
+
+/// `bits_to_bytes(bytes_to_bits(x)) == x`, and the reverse for bit strings padded to a whole byte
+pub fn util_bits_round_trip(data: &[u8]) {
+    assert_eq!(bit::bytes_to_bits(data).len(), data.len() * 8);
+    round_trip(
+        "bytes_to_bits",
+        &data.to_vec(),
+        |d| bit::bytes_to_bits(d),
+        |bits| Some(bit::bits_to_bytes(&bits)),
+    );
+
+    // Reinterpret the input as one bit per byte
+    let bits: Vec<bool> = data.iter().map(|b| b & 1 == 1).collect();
+    let bytes = bit::bits_to_bytes(&bits);
+    assert_eq!(bytes.len(), (bits.len() + 7) / 8);
+    round_trip(
+        "bits_to_bytes",
+        &bits,
+        |b| bit::bits_to_bytes(b),
+        |bytes| {
+            bit::bytes_to_bits(&bytes)
+                .get(..bits.len())
+                .map(<[bool]>::to_vec)
+        },
+    );
+    let unpacked = bit::bytes_to_bits(&bytes);
+    assert!(
+        unpacked[bits.len()..].iter().all(|b| !b),
+        "padding bits must be unset"
+    );
+}
+
+/// `bits_to_uint(uint_to_bits(v, n)) == v` truncated to `n` bits, for every `n` up to the full
+/// 64 bits of `usize` on the hosts the engines run on
+pub fn util_uint_bits_round_trip(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let bit_count = reader.read_u8() as usize % 65;
+    let value = reader.read_u64() as usize;
+    let bits = bit::uint_to_bits(value, bit_count);
+    assert_eq!(bits.len(), bit_count);
+    // `1 << 64` overflows, all bits are kept then
+    let mask = 1usize
+        .checked_shl(bit_count as u32)
+        .map_or(usize::MAX, |bit| bit - 1);
+    assert_eq!(
+        bits,
+        bit::uint_to_bits(value & mask, bit_count),
+        "bits above the count must be dropped"
+    );
+    round_trip(
+        "uint_to_bits",
+        &(value & mask),
+        |&v| bit::uint_to_bits(v, bit_count),
+        |bits| Some(bit::bits_to_uint(&bits)),
+    );
+}
+
+/// A message exercising the serde data model for `MessageFormat`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FuzzMessage {
+    pub id: u64,
+    pub height: u32,
+    pub flag: bool,
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub tags: Vec<String>,
+    pub maturity: Option<u16>,
+}
+
+impl FuzzMessage {
+    fn from_reader(reader: &mut DataReader) -> Self {
+        let id = reader.read_u64();
+        let height = reader.read_u32();
+        let flag = reader.read_bool();
+        let name = reader.read_string(64);
+        let payload = reader.read_vec(256);
+        let tags = (0..reader.read_u8() % 8)
+            .map(|_| reader.read_string(16))
+            .collect();
+        let maturity = if reader.read_bool() {
+            Some(reader.read_u16())
+        } else {
+            None
+        };
+        Self {
+            id,
+            height,
+            flag,
+            name,
+            payload,
+            tags,
+            maturity,
+        }
+    }
+}
+
+/// decode(encode(x)) == x for the binary, JSON and base64 message formats
+pub fn util_message_format_round_trip(data: &[u8]) {
+    let msg = FuzzMessage::from_reader(&mut DataReader::new(data));
+
+    message_format_round_trip(&msg);
+}
+
+/// Garbage input must fail gracefully, and anything that does decode must re-encode losslessly
+pub fn util_message_format_garbage(data: &[u8]) {
+    if let Ok(msg) = FuzzMessage::from_binary(data) {
+        message_format_round_trip(&msg);
+    }
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Ok(msg) = FuzzMessage::from_json(s) {
+            message_format_round_trip(&msg);
+        }
+        if let Ok(msg) = FuzzMessage::from_base64(s) {
+            message_format_round_trip(&msg);
+        }
+    }
+}
+
+/// `msg` must survive the binary, JSON and base64 formats unchanged
+fn message_format_round_trip(msg: &FuzzMessage) {
+    round_trip(
+        "binary",
+        msg,
+        |m| never_errors_on_valid("to_binary", m.to_binary()),
+        |b| FuzzMessage::from_binary(&b).ok(),
+    );
+    round_trip(
+        "JSON",
+        msg,
+        |m| never_errors_on_valid("to_json", m.to_json()),
+        |s| FuzzMessage::from_json(&s).ok(),
+    );
+    round_trip(
+        "base64",
+        msg,
+        |m| never_errors_on_valid("to_base64", m.to_base64()),
+        |s| FuzzMessage::from_base64(&s).ok(),
+    );
+}
+
+/// `ExtendBytes` must append the raw encoding of each value: bytes as they are, strings as
+/// UTF-8, integers little-endian and bools as one byte. Hashing inputs built from several values
+/// are then the concatenation of those encodings.
+pub fn util_extend_bytes(data: &[u8]) {
+    let mut reader = DataReader::new(data);
+    let num = reader.read_u64();
+    let flag = reader.read_bool();
+    let bytes = reader.read_vec(256);
+    let s = String::from_utf8_lossy(reader.rest()).into_owned();
+
+    assert_eq!(
+        appended(&bytes),
+        bytes,
+        "bytes must be appended as they are"
+    );
+    assert_eq!(
+        appended(&s),
+        s.as_bytes(),
+        "strings must append their UTF-8 bytes"
+    );
+    assert_eq!(
+        appended(&num),
+        num.to_le_bytes(),
+        "integers must append their little-endian bytes"
+    );
+    assert_eq!(
+        appended(&flag),
+        [flag as u8],
+        "bools must append a single 0 or 1 byte"
+    );
+
+    let mut combined = Vec::new();
+    bytes.append_raw_bytes(&mut combined);
+    num.append_raw_bytes(&mut combined);
+    flag.append_raw_bytes(&mut combined);
+    s.append_raw_bytes(&mut combined);
+    let mut expected = bytes.clone();
+    expected.extend_from_slice(&num.to_le_bytes());
+    expected.push(flag as u8);
+    expected.extend_from_slice(s.as_bytes());
+    assert_eq!(
+        combined, expected,
+        "appending values must concatenate their encodings"
+    );
+}
+
+/// The bytes `value` appends to an empty buffer
+fn appended<T: ExtendBytes>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.append_raw_bytes(&mut buf);
+    buf
+}
+
+/// `from_hex(to_hex(x)) == x` for any byte string, in both cases
+pub fn util_hex_round_trip(data: &[u8]) {
+    let data = data.to_vec();
+    assert_eq!(hex::to_hex(&data).len(), data.len() * 2);
+    round_trip(
+        "to_hex",
+        &data,
+        |d| hex::to_hex(d),
+        |s| hex::from_hex(&s).ok(),
+    );
+    round_trip(
+        "to_hex uppercase",
+        &data,
+        |d| hex::to_hex(d).to_uppercase(),
+        |s| hex::from_hex(&s).ok(),
+    );
+    round_trip(
+        "to_hex prefixed",
+        &data,
+        |d| format!("0x{}", hex::to_hex(d)),
+        |s| hex::from_hex(&s).ok(),
+    );
+}
+
+/// Compares `tari_utilities::hex` against the `hex` crate.
+///
+/// `from_hex` trims whitespace and strips a leading `0x`, so the input is normalised the same
+/// way before handing it to `hex::decode`. Everything else (case handling, odd lengths, invalid
+/// characters) must agree.
+pub fn util_hex_differential(data: &[u8]) {
+    assert_eq!(hex::to_hex(data), ::hex::encode(data));
+    if let Ok(s) = std::str::from_utf8(data) {
+        let trimmed = s.trim();
+        let stripped = if trimmed.starts_with("0x") {
+            &trimmed[2..]
+        } else {
+            trimmed
+        };
+        let ours = hex::from_hex(s).ok();
+        let theirs = ::hex::decode(stripped).ok();
+        assert_eq!(
+            ours, theirs,
+            "from_hex disagrees with hex::decode for {:?}",
+            s
+        );
+    }
+}
+
+/// Checks that `T` honours the `ByteArray` contract for arbitrary input.
+///
+/// `len` is the fixed encoding size of `T`, or `None` for variable length types. Any input of
+/// the wrong size must be rejected, and anything accepted must survive a `from_bytes` ->
+/// `as_bytes` round-trip without changing.
+pub fn byte_array_round_trip<T: ByteArray>(data: &[u8], len: Option<usize>) {
+    let value = match T::from_bytes(data) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    if let Some(len) = len {
+        assert_eq!(
+            data.len(),
+            len,
+            "from_bytes accepted an input of the wrong length"
+        );
+        assert_eq!(
+            value.as_bytes().len(),
+            len,
+            "as_bytes returned the wrong length"
+        );
+    }
+    let bytes = value.as_bytes().to_vec();
+    round_trip(
+        "from_bytes -> as_bytes",
+        &bytes,
+        |b| T::from_bytes(b).ok(),
+        |v| v.map(|v| v.as_bytes().to_vec()),
+    );
+    round_trip(
+        "from_vec -> to_vec",
+        &bytes,
+        |b| T::from_vec(b).ok(),
+        |v| v.map(|v| v.to_vec()),
+    );
+}
+
+/// Registers a `byte_array_round_trip` target per `ByteArray` implementer.
+macro_rules! byte_array_targets {
+    ($($name:ident => $ty:ty, $len:expr;)*) => {
+        register_targets! {
+            BYTE_ARRAY_TARGETS, BYTE_ARRAY_TARGET_FNS;
+            $($name => |data: &[u8]| byte_array_round_trip::<$ty>(data, $len);)*
+        }
+    };
+}
+
+// One line per `ByteArray` implementer
+byte_array_targets! {
+    util_ba_secret_key => RistrettoSecretKey, Some(32);
+    util_ba_public_key => RistrettoPublicKey, Some(32);
+    util_ba_commitment => PedersenCommitment, Some(32);
+    util_ba_hash => Vec<u8>, None;
+    util_ba_array_32 => [u8; 32], Some(32);
+}
+
+/// Checks the `Hex` trait for `T`: anything `from_hex` accepts must re-encode to the same
+/// lowercase hex, and `to_hex` of any valid value must decode back to it.
+pub fn hex_trait_round_trip<T: Hex + ByteArray>(data: &[u8]) {
+    if let Ok(value) = T::from_bytes(data) {
+        assert_eq!(value.to_hex(), ::hex::encode(value.as_bytes()));
+        hex_trait_value_round_trip(&value);
+    }
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Ok(value) = T::from_hex(s) {
+            hex_trait_value_round_trip(&value);
+        }
+    }
+}
+
+/// `from_hex(to_hex(value))` must hold the same bytes as `value`
+fn hex_trait_value_round_trip<T: Hex + ByteArray>(value: &T) {
+    round_trip(
+        "Hex",
+        &value.to_vec(),
+        |_| value.to_hex(),
+        |s| T::from_hex(&s).ok().map(|v| v.to_vec()),
+    );
+}
+
+/// Registers a `hex_trait_round_trip` target per `Hex` implementer.
+macro_rules! hex_trait_targets {
+    ($($name:ident => $ty:ty;)*) => {
+        register_targets! {
+            HEX_TRAIT_TARGETS, HEX_TRAIT_TARGET_FNS;
+            $($name => hex_trait_round_trip::<$ty>;)*
+        }
+    };
+}
+
+hex_trait_targets! {
+    util_hex_secret_key => RistrettoSecretKey;
+    util_hex_public_key => RistrettoPublicKey;
+    util_hex_commitment => PedersenCommitment;
 }
@@ -0,0 +1,225 @@
+//! __FUZZ_GENERATE_COMMENT__
+
+extern crate fuzz_targets;
+
+use std::{env, fs, path::PathBuf, process, str::FromStr, time::Duration};
+
+use fuzz_targets::{
+    __FUZZ_CLI_TARGET__ as fuzz_target,
+    alloc::{run_with_budget, BudgetAllocator},
+};
+use libafl::{
+    bolts::{
+        current_nanos,
+        launcher::Launcher,
+        os::Cores,
+        rands::StdRand,
+        shmem::{ShMemProvider, StdShMemProvider},
+        tuples::{tuple_list, Merge, Named},
+        AsSlice,
+    },
+    corpus::{
+        Corpus, InMemoryCorpus, IndexesLenTimeMinimizerCorpusScheduler, OnDiskCorpus,
+        PowerQueueCorpusScheduler, Testcase,
+    },
+    events::{EventConfig, EventFirer, EventRestarter},
+    executors::{inprocess::InProcessExecutor, ExitKind, TimeoutExecutor},
+    feedback_or,
+    feedbacks::{CrashFeedback, Feedback, MapFeedbackState, MaxMapFeedback, TimeFeedback},
+    fuzzer::{Fuzzer, StdFuzzer},
+    inputs::{BytesInput, HasTargetBytes},
+    monitors::MultiMonitor,
+    mutators::{
+        scheduled::{havoc_mutations, StdScheduledMutator},
+        token_mutations::{tokens_mutations, I2SRandReplace},
+    },
+    observers::{HitcountsMapObserver, ObserversTuple, StdMapObserver, TimeObserver},
+    stages::{
+        calibrate::CalibrationStage,
+        power::{PowerMutationalStage, PowerSchedule},
+        StdMutationalStage, TracingStage,
+    },
+    state::{HasClientPerfMonitor, HasCorpus, HasExecutions, StdState},
+    Error,
+};
+use libafl_targets::{CmpLogObserver, CMPLOG_MAP, EDGES_MAP, MAX_EDGES_NUM};
+
+#[global_allocator]
+static ALLOC: BudgetAllocator = BudgetAllocator;
+
+// Settings passed by the runner
+const CORES_ENV: &str = "TARI_LIBAFL_CORES";
+const BROKER_PORT_ENV: &str = "TARI_LIBAFL_BROKER_PORT";
+const TIMEOUT_MS_ENV: &str = "TARI_LIBAFL_TIMEOUT_MS";
+const RUNS_ENV: &str = "TARI_LIBAFL_RUNS";
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+/// Saves inputs that time out to the hangs dir rather than the objective corpus, so hangs are
+/// kept apart from crashes. Never counts as an objective itself.
+struct HangFeedback {
+    hangs: OnDiskCorpus<BytesInput>,
+}
+
+impl Named for HangFeedback {
+    fn name(&self) -> &str {
+        "HangFeedback"
+    }
+}
+
+impl<S: HasClientPerfMonitor> Feedback<BytesInput, S> for HangFeedback {
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        input: &BytesInput,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<BytesInput>,
+        OT: ObserversTuple<BytesInput, S>,
+    {
+        if *exit_kind == ExitKind::Timeout {
+            self.hangs.add(Testcase::new(input.clone()))?;
+        }
+        Ok(false)
+    }
+}
+
+fn run_one(data: &[u8]) {
+    run_with_budget(data, || {
+        let _ = fuzz_target(data);
+    });
+}
+
+fn main() {
+    let args: Vec<PathBuf> = env::args_os().skip(1).map(PathBuf::from).collect();
+    match args.as_slice() {
+        // Replay a single input, e.g. a crash or a hang being triaged
+        [input] if input.is_file() => {
+            let data = fs::read(input).expect("unable to read the input");
+            run_one(&data);
+        }
+        [corpus_dir, artifact_dir, hangs_dir] => {
+            fuzz(corpus_dir.clone(), artifact_dir.clone(), hangs_dir.clone())
+        }
+        _ => {
+            eprintln!("usage: <corpus dir> <artifact dir> <hangs dir> | <input>");
+            process::exit(2);
+        }
+    }
+}
+
+/// Fuzz on every configured core, clients share their finds through the broker
+fn fuzz(corpus_dir: PathBuf, artifact_dir: PathBuf, hangs_dir: PathBuf) {
+    let cores = Cores::from_cmdline(&env::var(CORES_ENV).unwrap_or_else(|_| "0".to_string()))
+        .expect("invalid core list");
+    let broker_port: u16 = env_or(BROKER_PORT_ENV, 1337);
+    // A zero timer is never armed, so 0 disables the timeout
+    let timeout = Duration::from_millis(env_or(TIMEOUT_MS_ENV, 1000));
+    // Executions per client, 0 to fuzz until stopped
+    let runs: usize = env_or(RUNS_ENV, 0);
+
+    let shmem_provider = StdShMemProvider::new().expect("unable to get shared memory");
+    let monitor = MultiMonitor::new(|s| println!("{}", s));
+
+    let mut run_client = |state: Option<StdState<_, _, _, _, _>>, mut mgr, _core_id| {
+        // Edge coverage from the sancov instrumentation, comparisons for input-to-state replacement
+        let edges = unsafe { &mut EDGES_MAP[0..MAX_EDGES_NUM] };
+        let edges_observer = HitcountsMapObserver::new(StdMapObserver::new("edges", edges));
+        let time_observer = TimeObserver::new("time");
+        let cmplog_observer = CmpLogObserver::new("cmplog", unsafe { &mut CMPLOG_MAP }, true);
+
+        let feedback_state = MapFeedbackState::with_observer(&edges_observer);
+        let feedback = feedback_or!(
+            MaxMapFeedback::new_tracking(&feedback_state, &edges_observer, true, false),
+            TimeFeedback::new_with_observer(&time_observer)
+        );
+        // Crashes are saved to the artifact dir, timeouts to the hangs dir
+        let objective = feedback_or!(
+            CrashFeedback::new(),
+            HangFeedback {
+                hangs: OnDiskCorpus::new(hangs_dir.clone()).unwrap(),
+            }
+        );
+
+        let mut state = state.unwrap_or_else(|| {
+            StdState::new(
+                StdRand::with_seed(current_nanos()),
+                InMemoryCorpus::new(),
+                OnDiskCorpus::new(artifact_dir.clone()).unwrap(),
+                tuple_list!(feedback_state),
+            )
+        });
+
+        let calibration = CalibrationStage::new(&mut state, &edges_observer);
+        let mutator = StdScheduledMutator::new(havoc_mutations().merge(tokens_mutations()));
+        let power = PowerMutationalStage::new(mutator, PowerSchedule::FAST, &edges_observer);
+        let i2s = StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(I2SRandReplace::new())));
+
+        let scheduler =
+            IndexesLenTimeMinimizerCorpusScheduler::new(PowerQueueCorpusScheduler::new());
+        let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
+
+        let mut harness = |input: &BytesInput| {
+            let target = input.target_bytes();
+            run_one(target.as_slice());
+            ExitKind::Ok
+        };
+        let mut tracing_harness = harness;
+
+        let mut executor = TimeoutExecutor::new(
+            InProcessExecutor::new(
+                &mut harness,
+                tuple_list!(edges_observer, time_observer),
+                &mut fuzzer,
+                &mut state,
+                &mut mgr,
+            )?,
+            timeout,
+        );
+        let tracing = TracingStage::new(InProcessExecutor::new(
+            &mut tracing_harness,
+            tuple_list!(cmplog_observer),
+            &mut fuzzer,
+            &mut state,
+            &mut mgr,
+        )?);
+
+        if state.corpus().count() < 1 {
+            state.load_initial_inputs(&mut fuzzer, &mut executor, &mut mgr, &[corpus_dir.clone()])?;
+        }
+
+        let mut stages = tuple_list!(calibration, tracing, i2s, power);
+        if runs > 0 {
+            // `fuzz_loop_for` would count scheduled corpus entries, each of which runs every stage
+            while *state.executions() < runs {
+                fuzzer.fuzz_one(&mut stages, &mut executor, &mut state, &mut mgr)?;
+            }
+            mgr.on_restart(&mut state)?;
+            process::exit(0);
+        }
+        fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut mgr)?;
+        Ok(())
+    };
+
+    match Launcher::builder()
+        .shmem_provider(shmem_provider)
+        .configuration(EventConfig::from_name("default"))
+        .monitor(monitor)
+        .run_client(&mut run_client)
+        .cores(&cores)
+        .broker_port(broker_port)
+        .build()
+        .launch()
+    {
+        Ok(()) | Err(Error::ShuttingDown) => (),
+        Err(e) => panic!("LibAFL failed: {:?}", e),
+    }
+}
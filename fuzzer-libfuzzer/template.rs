@@ -7,8 +7,16 @@
 extern crate libfuzzer_sys;
 extern crate fuzz_targets;
 
-use fuzz_targets::__FUZZ_CLI_TARGET__ as fuzz_target;
+use fuzz_targets::{
+    __FUZZ_CLI_TARGET__ as fuzz_target,
+    alloc::{run_with_budget, BudgetAllocator},
+};
+
+#[global_allocator]
+static ALLOC: BudgetAllocator = BudgetAllocator;
 
 fuzz_target!(|data: &[u8]| {
-    let _ = fuzz_target(data);
+    run_with_budget(data, || {
+        let _ = fuzz_target(data);
+    });
 });
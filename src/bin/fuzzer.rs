@@ -1,15 +1,29 @@
 use clap::AppSettings;
-use fuzz_targets::{check_target,TARGETS};
+use fuzz_targets::{alloc::BudgetAllocator, check_target, TARGETS};
 use log::{info, trace, warn, LevelFilter};
 use once_cell::sync::Lazy;
-use std::process::exit;
+use std::{path::PathBuf, process::exit};
 use structopt::StructOpt;
-use tarifuzzer::{corpus, runner, Fuzzer, Result, TariError};
+use tarifuzzer::{
+    build, corpus, doctor, inprocess, runner,
+    runner::{CargoFuzzCommand, RunOptions},
+    triage, Fuzzer, Result, TariError,
+};
+
+// Enforces the allocation budget of the in-process engine, idle otherwise
+#[global_allocator]
+static ALLOC: BudgetAllocator = BudgetAllocator;
 
 // Constants for our defaults
 const ENGINE_DEFAULT: &str = "Libfuzzer";
 const CORPUS_ITEMS: usize = 25;
 static CORPUS_ITEMS_STR: Lazy<String> = Lazy::new(|| CORPUS_ITEMS.to_string());
+const RSS_LIMIT_MB: usize = 2048;
+static RSS_LIMIT_MB_STR: Lazy<String> = Lazy::new(|| RSS_LIMIT_MB.to_string());
+static ALLOC_BUDGET_MB_STR: Lazy<String> =
+    Lazy::new(|| fuzz_targets::alloc::DEFAULT_ALLOC_BUDGET_MB.to_string());
+const TIMEOUT_MS: usize = 1000;
+static TIMEOUT_MS_STR: Lazy<String> = Lazy::new(|| TIMEOUT_MS.to_string());
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "tari-fuzzer", global_settings = &
@@ -42,13 +56,125 @@ enum Command {
         default_value = &CORPUS_ITEMS_STR,
         )]
         seeds: usize,
+        #[structopt(
+        long = "rss-limit-mb",
+        help = "Memory limit for the target process in MB, 0 for none.",
+        default_value = &RSS_LIMIT_MB_STR,
+        )]
+        rss_limit_mb: usize,
+        #[structopt(
+        long = "alloc-budget-mb",
+        help = "Memory a single input may hold at once in MB, 0 for none.",
+        default_value = &ALLOC_BUDGET_MB_STR,
+        )]
+        alloc_budget_mb: usize,
+        #[structopt(
+        long = "timeout-ms",
+        help = "Time a single input may take in milliseconds before it counts as a hang, 0 for the engine's default.",
+        default_value = &TIMEOUT_MS_STR,
+        )]
+        timeout_ms: usize,
+        #[structopt(
+        long = "runs",
+        help = "Number of inputs to run before stopping, 0 to run until a crash (Libfuzzer, Honggfuzz and Inprocess).",
+        default_value = "0",
+        )]
+        runs: usize,
+        #[structopt(
+        long = "cores",
+        help = "Number of cores to fuzz on, 0 for all (LibAfl).",
+        default_value = "1",
+        )]
+        cores: usize,
+        #[structopt(
+        long = "corpus-dir",
+        help = "Corpus directory to use instead of corpus-{target} in the engine's directory.",
+        parse(from_os_str),
+        )]
+        corpus_dir: Option<PathBuf>,
+        #[structopt(
+        long = "artifacts-dir",
+        help = "Directory for crashes and engine output instead of artifact-{target} in the engine's directory.",
+        parse(from_os_str),
+        )]
+        artifacts_dir: Option<PathBuf>,
     },
-    #[structopt(name = "build", about = "Build specified target")]
+    #[structopt(
+        name = "build",
+        about = "Build the instrumented binaries of targets without running them"
+    )]
     Build {
-        /// Engine to use (use `engines` to list them)
+        #[structopt(
+            name = "TARGET",
+            help = "A string fuzz target, repeat for more, all targets when not set",
+            short = "t",
+            number_of_values = 1
+        )]
+        targets: Vec<String>,
+        /// Engines to build for (use `engines` to list them)
+        #[structopt(name="ENGINE", help = "A fuzzing engine to build for, repeat for more",
+        case_insensitive=true, short = "e", number_of_values = 1, default_value=&ENGINE_DEFAULT)]
+        engines: Vec<Fuzzer>,
+        #[structopt(long = "force", help = "Rebuild even when nothing changed.")]
+        force: bool,
+    },
+    #[structopt(
+        name = "triage-hangs",
+        about = "Replay saved hangs and report the slowest function"
+    )]
+    TriageHangs {
+        #[structopt(
+            name = "TARGET",
+            help = "A string fuzz target",
+            required = true,
+            short = "t"
+        )]
+        target: String,
+        /// Engine that found the hangs
         #[structopt(name="ENGINE", help = "A fuzzing engine to use",
         case_insensitive=true, short = "e", default_value=&ENGINE_DEFAULT)]
         engine: Fuzzer,
+        #[structopt(long = "samples", help = "Stack samples per hang.", default_value = "20")]
+        samples: usize,
+        #[structopt(
+            long = "interval-ms",
+            help = "Milliseconds between stack samples.",
+            default_value = "100"
+        )]
+        interval_ms: u64,
+        #[structopt(
+            long = "artifacts-dir",
+            help = "The --artifacts-dir the hangs were fuzzed with, if any.",
+            parse(from_os_str)
+        )]
+        artifacts_dir: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "cargo-fuzz",
+        about = "Sync the cargo-fuzz layout of the Libfuzzer targets and run cargo fuzz on it"
+    )]
+    CargoFuzz {
+        #[structopt(
+            name = "COMMAND",
+            help = "sync, build, run, cmin, tmin or coverage",
+            case_insensitive = true,
+            possible_values = &CargoFuzzCommand::variants()
+        )]
+        command: CargoFuzzCommand,
+        #[structopt(name = "TARGET", help = "A string fuzz target", short = "t")]
+        target: Option<String>,
+        #[structopt(name = "ARGS", help = "Passed on to cargo fuzz", last = true)]
+        args: Vec<String>,
+    },
+    #[structopt(
+        name = "doctor",
+        about = "Check the tools and checkouts the engines need and how to fix what is missing"
+    )]
+    Doctor {
+        /// Engine to check, all of them when not set
+        #[structopt(name = "ENGINE", help = "A fuzzing engine to check",
+        case_insensitive = true, short = "e")]
+        engine: Option<Fuzzer>,
     },
     #[structopt(name = "list-targets", about = "Get the list of available targets")]
     ListTargets {},
@@ -74,6 +200,12 @@ enum Command {
         /// Engine to use (use `engines` to list them)
         #[structopt(name="ENGINE", help = "A fuzzing engine to use", short = "e", default_value=&ENGINE_DEFAULT)]
         engine: Fuzzer,
+        #[structopt(
+        long = "corpus-dir",
+        help = "Corpus directory to use instead of corpus-{target} in the engine's directory.",
+        parse(from_os_str),
+        )]
+        corpus_dir: Option<PathBuf>,
     },
 }
 
@@ -97,37 +229,80 @@ fn run(opt: Opt) -> Result<()> {
             target,
             engine,
             seeds,
+            rss_limit_mb,
+            alloc_budget_mb,
+            timeout_ms,
+            runs,
+            cores,
+            corpus_dir,
+            artifacts_dir,
         } => {
             info!(
                 "Fuzzing job started for target: {:?} using engine {:?}",
                 &target, &engine
             );
             check_target(&target);
+            let options = RunOptions {
+                seeds,
+                rss_limit_mb,
+                alloc_budget_mb,
+                timeout_ms,
+                runs,
+                cores,
+                corpus_dir,
+                artifacts_dir,
+            };
             match engine {
-                Fuzzer::Afl => runner::run_afl(&target, engine, seeds).is_err(),
-                Fuzzer::Honggfuzz => runner::run_honggfuzz(&target, engine, seeds).is_err(),
-                _ => runner::run_libfuzzer(&target, engine, seeds).is_err(),
+                Fuzzer::Afl => runner::run_afl(&target, engine, &options).is_err(),
+                Fuzzer::Honggfuzz => runner::run_honggfuzz(&target, engine, &options).is_err(),
+                Fuzzer::LibAfl => runner::run_libafl(&target, engine, &options).is_err(),
+                Fuzzer::Inprocess => match inprocess::run_inprocess(&target, engine, &options) {
+                    Err(e) => {
+                        warn!("{:#}", e);
+                        true
+                    }
+                    Ok(()) => false,
+                },
+                _ => runner::run_libfuzzer(&target, engine, &options).is_err(),
             };
         }
         Command::Build {
-            engine,
+            targets,
+            engines,
+            force,
         } => {
-            
-            //check_target(&target);
-           for target in &*TARGETS {
-            info!(
-                "Building targets: {}", &target
-            );
-            let artifact_dir = format!("./fuzzer-libfuzzer/artifact-{}", &target);
-            runner::build_libfuzzer(&target, engine, &artifact_dir).is_err();
-               
-           }
-
+            let targets: Vec<String> = if targets.is_empty() {
+                TARGETS.iter().map(|target| target.to_string()).collect()
+            } else {
+                targets
+            };
+            let mut built = 0;
+            let mut failed = 0;
+            for engine in &engines {
+                if *engine == Fuzzer::Inprocess {
+                    info!(
+                        "The in-process engine runs the targets built into this crate, nothing to build"
+                    );
+                    continue;
+                }
+                for target in &targets {
+                    check_target(target);
+                    match build::build_target(target, *engine, force) {
+                        Ok(_) => built += 1,
+                        Err(e) => {
+                            warn!("{:#}", e);
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+            info!("Built {} of {} target(s)", built, built + failed);
         }
         Command::GenCorpus {
             target,
             engine,
             seeds,
+            corpus_dir,
         } => {
             trace!(
                 "Generating {:?} seeds for target: {:?} using engine {:?}",
@@ -139,7 +314,10 @@ fn run(opt: Opt) -> Result<()> {
                 info!("Seed count is set to 0, no seeds will be created!")
             };
             check_target(&target);
-            let _corpus_dir = corpus::gen_corpus(&target, engine, seeds);
+            let _corpus_dir = match corpus_dir {
+                Some(corpus_dir) => corpus::gen_corpus_in(&target, corpus_dir, seeds),
+                None => corpus::gen_corpus(&target, engine, seeds),
+            };
             //info!("{:?}", &corpus_dir);
             info!("Corpus generation completed!");
             //if corpus_dir.is_err() {
@@ -149,15 +327,57 @@ fn run(opt: Opt) -> Result<()> {
             //  info!("Corpus generation completed!");
             // }
         }
+        Command::TriageHangs {
+            target,
+            engine,
+            samples,
+            interval_ms,
+            artifacts_dir,
+        } => {
+            info!("Triaging hangs for target: {:?} found by {:?}", &target, &engine);
+            check_target(&target);
+            if let Err(e) = triage::triage_hangs(
+                &target,
+                engine,
+                artifacts_dir.as_deref(),
+                samples,
+                interval_ms,
+            ) {
+                warn!("{:#}", e);
+            }
+        }
+        Command::CargoFuzz {
+            command,
+            target,
+            args,
+        } => {
+            info!("Running cargo fuzz {:?} on target: {:?}", &command, &target);
+            if let Some(target) = &target {
+                check_target(target);
+            }
+            if let Err(e) = runner::run_cargo_fuzz(command, target.as_deref(), &args) {
+                warn!("{:#}", e);
+            }
+        }
+        Command::Doctor { engine } => {
+            if let Err(e) = doctor::run_doctor(engine) {
+                warn!("{:#}", e);
+                exit(1);
+            }
+        }
         Command::ListEngines {} => {
             info!(
                 "Current supported engines are:\n\
                 \u{1F004}{:?} => https://github.com/rust-fuzz/afl.rs \n\
                 \u{1F0CF}{:?} => https://github.com/rust-fuzz/libfuzzer\n\
-                \u{1F335}{:?} => https://honggfuzz.dev/ \n",
+                \u{1F335}{:?} => https://honggfuzz.dev/ \n\
+                \u{1F41E}{:?} => https://github.com/AFLplusplus/LibAFL\n\
+                \u{1F9EA}{:?} => built in, runs on stable Rust without extra tools\n",
                 Fuzzer::Afl,
                 Fuzzer::Libfuzzer,
-                Fuzzer::Honggfuzz
+                Fuzzer::Honggfuzz,
+                Fuzzer::LibAfl,
+                Fuzzer::Inprocess
             );
         }
         Command::ListTargets {} => {
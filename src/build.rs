@@ -0,0 +1,330 @@
+//! Building the engines' instrumented binaries without running them.
+//!
+//! Each build is fingerprinted with what went into it: target, engine, sanitizer, flags,
+//! toolchain and the sources under test. The binary is kept under `build-cache/<fingerprint>/`
+//! in the engine's directory and run from there, so a build whose fingerprint is cached is
+//! skipped. Only the latest build of each target is kept.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    process::Command,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    engines::Fuzzer,
+    runner::{
+        engine_binary, pre_check, sync_cargo_fuzz_layout, target_features,
+        write_fuzz_target_source_file,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use cargo_metadata::{CargoOpt, MetadataCommand};
+use lazy_static::lazy_static;
+use log::{info, trace, warn};
+
+lazy_static! {
+    /// The host's target triple, as `rustc -vV` reports it. The engines build for the host and
+    /// name their output directories after it.
+    pub(crate) static ref TARGET_PLATFORM: String = {
+        let output = Command::new("rustc")
+            .arg("-vV")
+            .output()
+            .expect("Failed to run rustc");
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .expect("rustc -vV reports no host triple")
+            .to_string()
+    };
+}
+
+// FIXME: The -C codegen-units=1 and -C incremental=..
+// below seem to workaround some difficult issues in Rust nightly
+// https://github.com/rust-lang/rust/issues/53945.
+// If this is ever fixed remember to remove the fuzz-incremental
+// entry from .gitignore.
+const LIBFUZZER_RUST_FLAGS: &str = " --cfg fuzzing -C codegen-units=1 -C incremental=fuzz-incremental -C \
+     passes=sancov -C llvm-args=-sanitizer-coverage-level=4 -C llvm-args=-sanitizer-coverage-trace-compares -C \
+     llvm-args=-sanitizer-coverage-inline-8bit-counters -C llvm-args=-sanitizer-coverage-trace-geps -C \
+     llvm-args=-sanitizer-coverage-prune-blocks=0 -C debug-assertions=on -C debuginfo=0 -C opt-level=3 -Z \
+     sanitizer=address";
+
+// Edge counters feed the coverage map, comparisons feed CmpLog, see the `libafl_targets`
+// features in fuzzer-libafl/Cargo.toml
+const LIBAFL_RUST_FLAGS: &str =
+    " --cfg fuzzing -C passes=sancov -C llvm-args=-sanitizer-coverage-level=3 -C \
+     llvm-args=-sanitizer-coverage-trace-pc-guard -C llvm-args=-sanitizer-coverage-trace-compares \
+     -C debug-assertions=on";
+
+const HONGGFUZZ_RUST_FLAGS: &str = " -Z sanitizer=address";
+
+/// `RUSTFLAGS` the engine builds with, on top of the environment's
+fn rust_flags(fuzzer: Fuzzer) -> String {
+    let mut rust_flags = env::var("RUSTFLAGS").unwrap_or_default();
+    rust_flags.push_str(match fuzzer {
+        Fuzzer::Libfuzzer => LIBFUZZER_RUST_FLAGS,
+        Fuzzer::Honggfuzz => HONGGFUZZ_RUST_FLAGS,
+        Fuzzer::LibAfl => LIBAFL_RUST_FLAGS,
+        Fuzzer::Afl | Fuzzer::Inprocess => "",
+    });
+    rust_flags
+}
+
+/// `HFUZZ_BUILD_ARGS` for `target`, `cargo hfuzz` has no other way to pick the binary
+fn hfuzz_build_args(target: &str) -> String {
+    let mut hfuzz_build_args = env::var("HFUZZ_BUILD_ARGS").unwrap_or_default();
+    hfuzz_build_args.push_str(" --bin ");
+    hfuzz_build_args.push_str(target);
+    for arg in target_features(target) {
+        hfuzz_build_args.push(' ');
+        hfuzz_build_args.push_str(arg);
+    }
+    hfuzz_build_args
+}
+
+/// The sanitizer the engine builds with
+fn sanitizer(fuzzer: Fuzzer) -> &'static str {
+    match fuzzer {
+        Fuzzer::Libfuzzer | Fuzzer::Honggfuzz => "address",
+        Fuzzer::Afl | Fuzzer::LibAfl | Fuzzer::Inprocess => "none",
+    }
+}
+
+/// The binary the build produces, `None` for engines without one
+fn built_binary(fuzzer: Fuzzer, target: &str) -> Option<PathBuf> {
+    match fuzzer {
+        // `engine_binary` is the debug build, which triage replays with
+        Fuzzer::Honggfuzz => Some(
+            fuzzer
+                .directory()
+                .join("hfuzz_target")
+                .join(&*TARGET_PLATFORM)
+                .join("release")
+                .join(target),
+        ),
+        _ => engine_binary(fuzzer, target),
+    }
+}
+
+/// The cargo command building `target`, with the environment it needs
+fn build_command(fuzzer: Fuzzer, target: &str) -> Result<Command> {
+    let mut command = Command::new("cargo");
+    match fuzzer {
+        Fuzzer::Libfuzzer => {
+            if !cfg!(any(target_os = "linux", target_os = "macos")) {
+                return Err(anyhow!("libfuzzer-sys only supports Linux and macOS"));
+            }
+            let mut asan_options = env::var("ASAN_OPTIONS").unwrap_or_default();
+            asan_options.push_str(" detect_odr_violation=0");
+            command
+                .args(&["build", "--target", &TARGET_PLATFORM, "--bin", target])
+                .args(target_features(target))
+                .env("ASAN_OPTIONS", &asan_options);
+        }
+        Fuzzer::Afl => {
+            pre_check(
+                Command::new("cargo").args(&["afl", "--version"]),
+                "cargo install afl",
+            )?;
+            command
+                .args(&["afl", "build", "--bin", target])
+                .args(target_features(target));
+        }
+        Fuzzer::Honggfuzz => {
+            pre_check(
+                Command::new("cargo").args(&["hfuzz", "version"]),
+                "cargo install honggfuzz --version 0.5.45",
+            )?;
+            command
+                .args(&["hfuzz", "build"])
+                .env("HFUZZ_BUILD_ARGS", hfuzz_build_args(target));
+        }
+        Fuzzer::LibAfl => {
+            command
+                .args(&["build", "--release", "--bin", target])
+                .args(target_features(target));
+        }
+        Fuzzer::Inprocess => {
+            return Err(anyhow!(
+                "the in-process engine is built into this crate, there is nothing to build"
+            ))
+        }
+    }
+    command
+        .env("RUSTFLAGS", rust_flags(fuzzer))
+        .current_dir(fuzzer.directory());
+    Ok(command)
+}
+
+/// Directories of the path dependencies the engine builds, `fuzz_targets` and the crates of the
+/// checkouts next to the workspace it uses. Git and registry dependencies, like the baseline of
+/// the differential targets, are pinned by the engine's Cargo.lock instead.
+fn local_packages(fuzzer: Fuzzer) -> Result<Vec<PathBuf>> {
+    let metadata = MetadataCommand::new()
+        .manifest_path(fuzzer.directory().join("Cargo.toml"))
+        .features(CargoOpt::AllFeatures)
+        .exec()
+        .context(format!("unable to read the dependencies of {}", fuzzer))?;
+    let mut dirs: Vec<PathBuf> = metadata
+        .packages
+        .iter()
+        .filter(|package| package.source.is_none())
+        .filter_map(|package| package.manifest_path.parent().map(Path::to_path_buf))
+        // The engine's own files are hashed one by one, its directory holds the build output
+        .filter(|dir| *dir != fuzzer.directory())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    Ok(dirs)
+}
+
+/// Hash the size and modification time of every file under `dir`, skipping build output and
+/// hidden directories. Entries that cannot be read are skipped, cargo reports them if it needs
+/// them.
+fn hash_tree(dir: &Path, hasher: &mut DefaultHasher) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if name.starts_with('.') || name == "target" {
+            continue;
+        }
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            hash_tree(&path, hasher);
+        } else {
+            path.hash(hasher);
+            metadata.len().hash(hasher);
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .hash(hasher);
+        }
+    }
+}
+
+/// Everything the binary of `target` depends on, see the module docs
+fn fingerprint(fuzzer: Fuzzer, target: &str, command: &Command) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    target.hash(&mut hasher);
+    fuzzer.to_string().hash(&mut hasher);
+    sanitizer(fuzzer).hash(&mut hasher);
+    // Arguments and environment, flags included
+    format!("{:?}", command).hash(&mut hasher);
+    rust_flags(fuzzer).hash(&mut hasher);
+    if fuzzer == Fuzzer::Honggfuzz {
+        hfuzz_build_args(target).hash(&mut hasher);
+    }
+    let rustc = Command::new("rustc")
+        .arg("--version")
+        .output()
+        .context("Failed to run rustc")?;
+    rustc.stdout.hash(&mut hasher);
+
+    for file in &["Cargo.toml", "Cargo.lock", "template.rs"] {
+        fs::read(fuzzer.directory().join(file))
+            .ok()
+            .hash(&mut hasher);
+    }
+    for dir in local_packages(fuzzer)? {
+        hash_tree(&dir, &mut hasher);
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Where the binary of `target` built with `fingerprint` is kept
+fn cached_binary(fuzzer: Fuzzer, target: &str, fingerprint: &str) -> PathBuf {
+    fuzzer
+        .directory()
+        .join("build-cache")
+        .join(fingerprint)
+        .join(target)
+}
+
+/// Drop the builds of `target` with other fingerprints than `fingerprint`, they are stale
+fn prune_cache(fuzzer: Fuzzer, target: &str, fingerprint: &str) {
+    let entries = match fs::read_dir(fuzzer.directory().join("build-cache")) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for dir in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        // The target is part of the fingerprint, so each directory holds a single binary
+        if dir.ends_with(fingerprint) || !dir.join(target).exists() {
+            continue;
+        }
+        trace!("Removing stale build {}", dir.display());
+        if let Err(err) = fs::remove_dir_all(&dir) {
+            warn!("unable to remove stale build {}: {}", dir.display(), err);
+        }
+    }
+}
+
+/// Build the instrumented binary of `target` for `fuzzer`, unless a build with the same
+/// fingerprint is cached or `force` is set, and return the path of the cached binary
+pub fn build_target(target: &str, fuzzer: Fuzzer, force: bool) -> Result<PathBuf> {
+    trace!("Getting ready to build {} for {}", target, fuzzer);
+    match fuzzer {
+        Fuzzer::Libfuzzer => sync_cargo_fuzz_layout()?,
+        Fuzzer::Inprocess => {}
+        _ => write_fuzz_target_source_file(&fuzzer, target)?,
+    }
+    let mut command = build_command(fuzzer, target)?;
+    let binary = built_binary(fuzzer, target)
+        .ok_or_else(|| anyhow!("{} does not build a binary for {}", fuzzer, target))?;
+    let fingerprint = fingerprint(fuzzer, target, &command)?;
+    let cached = cached_binary(fuzzer, target, &fingerprint);
+
+    if !force && cached.exists() {
+        info!("{} for {} is up to date, not rebuilding", target, fuzzer);
+        return Ok(cached);
+    }
+
+    info!("Building {} for {}", target, fuzzer);
+    let status = command
+        .spawn()
+        .context(format!("Failed to build {}", fuzzer))?
+        .wait()
+        .context(format!("Failed to complete building {}", fuzzer))?;
+    if !status.success() {
+        return Err(anyhow!(
+            "error building {} for {}, exit code {:?}",
+            target,
+            fuzzer,
+            status.code()
+        ));
+    }
+    if !binary.exists() {
+        return Err(anyhow!(
+            "{} built, but {} is missing",
+            target,
+            binary.display()
+        ));
+    }
+
+    if let Some(dir) = cached.parent() {
+        fs::create_dir_all(dir).context(format!("unable to create {}", dir.display()))?;
+    }
+    // Copied under another name first, an interrupted copy must not look cached
+    let partial = cached.with_extension("partial");
+    fs::copy(&binary, &partial).context(format!("unable to cache {}", binary.display()))?;
+    fs::rename(&partial, &cached).context(format!("unable to cache {}", binary.display()))?;
+    prune_cache(fuzzer, target, &fingerprint);
+    Ok(cached)
+}
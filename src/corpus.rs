@@ -1,10 +1,15 @@
 use crate::engines::Fuzzer;
 use crate::types;
 use anyhow::{Context, Result};
-use fuzz_targets::common::SeedGen;
+use fuzz_targets::{
+    common::SeedGen, diff_seed, encoding_seed, BYTE_ARRAY_TARGETS, DIFF_TARGETS, ENCODING_TARGETS,
+    HEX_TRAIT_TARGETS,
+};
 use log::{info, trace};
 use std::{
+    collections::hash_map::DefaultHasher,
     fs,
+    hash::{Hash, Hasher},
     io::Write,
     path::{Path, PathBuf},
 };
@@ -23,9 +28,36 @@ pub fn create_corpus_dir(base: impl AsRef<Path>, target: &str) -> Result<PathBuf
     Ok(corpus_dir)
 }
 
+/// Write a single seed to the corpus dir, named after its hex encoding.
+///
+/// Long seeds would exceed the file name limit, so they are named after a hash instead.
+fn write_seed(corpus_dir: &Path, seeds: &[u8]) -> Result<()> {
+    let mut name = hex::encode(seeds);
+    if name.len() > 128 {
+        let mut hasher = DefaultHasher::new();
+        seeds.hash(&mut hasher);
+        name = format!("{:016x}", hasher.finish());
+    }
+    let filename = corpus_dir.join(name);
+    let mut f = fs::File::create(&filename)
+        .with_context(|| format!("Failed to create file: {:?}", filename))?;
+    f.write_all(seeds)
+        .with_context(|| format!("Failed to write to file: {:?}", filename))?;
+    Ok(())
+}
+
 pub fn gen_corpus(target: &str, fuzzer: Fuzzer, num_items: usize) -> Result<PathBuf> {
-    trace!("Generating corpus");
     let corpus_dir = create_corpus_dir(fuzzer.directory(), target)?;
+    gen_corpus_in(target, corpus_dir, num_items)
+}
+
+/// Generate the corpus of `target` into `corpus_dir` instead of the engine's directory
+pub fn gen_corpus_in(target: &str, corpus_dir: PathBuf, num_items: usize) -> Result<PathBuf> {
+    trace!("Generating corpus");
+    fs::create_dir_all(&corpus_dir).context(format!(
+        "unable to create corpus dir {}",
+        corpus_dir.display()
+    ))?;
     let mut gen = SeedGen::new();
     let mut idx: usize = 0;
     match target {
@@ -45,6 +77,30 @@ pub fn gen_corpus(target: &str, fuzzer: Fuzzer, num_items: usize) -> Result<Path
             }
             Ok(corpus_dir)
         }
+        "mmr_proof_verify"
+        | "mmr_proof_forged"
+        | "mmr_mutable_ops"
+        | "mmr_change_tracker_ops"
+        | "mmr_pruned_root"
+        | "mmr_pruned_mutable_root"
+        | "mmr_leaf_hashes_range"
+        | "mmr_bitmap_deserialize"
+        | "mmr_leaf_nodes_deserialize"
+        | "core_trx_validate"
+        | "core_trx_sender_protocol"
+        | "core_pow_difficulty_arith"
+        | "core_pow_lwma"
+        | "core_pow_achieved_difficulty"
+        | "core_fee_calculate"
+        | "core_micro_tari_arith"
+        | "core_chain_mempool_ops" => {
+            while idx < num_items {
+                let seeds = types::gen_vec_u8(&mut gen);
+                write_seed(&corpus_dir, &seeds)?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
         //TO_HEX
         "util_to_hex" => {
             while idx < num_items {
@@ -128,6 +184,87 @@ pub fn gen_corpus(target: &str, fuzzer: Fuzzer, num_items: usize) -> Result<Path
             info!("Not generating seeds for this target!");
             Ok(corpus_dir)
         },
+        //HEX
+        "util_hex_round_trip" => {
+            while idx < num_items {
+                let seeds = types::gen_vec_u8(&mut gen);
+                write_seed(&corpus_dir, &seeds)?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        "util_hex_differential" => {
+            while idx < num_items {
+                let seeds = hex::encode(types::gen_vec_u8(&mut gen));
+                // Mix in the `0x` prefix and upper case variants `from_hex` has to handle
+                let seeds = match idx % 3 {
+                    0 => seeds,
+                    1 => format!("0x{}", seeds),
+                    _ => seeds.to_uppercase(),
+                };
+                write_seed(&corpus_dir, seeds.as_bytes())?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        t if HEX_TRAIT_TARGETS.contains(&t) => {
+            while idx < num_items {
+                let seeds = hex::encode(types::gen_array_u8_32(&mut gen));
+                write_seed(&corpus_dir, seeds.as_bytes())?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        //BIT, MESSAGE FORMAT, EXTEND BYTES
+        "util_bits_round_trip"
+        | "util_uint_bits_round_trip"
+        | "util_message_format_round_trip"
+        | "util_extend_bytes" => {
+            while idx < num_items {
+                let seeds = types::gen_vec_u8(&mut gen);
+                write_seed(&corpus_dir, &seeds)?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        "util_message_format_garbage" => {
+            while idx < num_items {
+                let seeds = types::gen_str(&mut gen);
+                write_seed(&corpus_dir, seeds.as_bytes())?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        //Binary encodings, seeded with a valid value
+        t if ENCODING_TARGETS.contains(&t) => {
+            while idx < num_items {
+                let n = types::gen_u64(&mut gen);
+                if let Some(seeds) = encoding_seed(t, n) {
+                    write_seed(&corpus_dir, &seeds)?;
+                }
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        //ByteArray implementers
+        t if BYTE_ARRAY_TARGETS.contains(&t) => {
+            while idx < num_items {
+                let seeds = types::gen_array_u8_32(&mut gen);
+                write_seed(&corpus_dir, &seeds)?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
+        //Differential targets, seeded with valid encodings where they decode one
+        t if DIFF_TARGETS.contains(&t) => {
+            while idx < num_items {
+                let amount = types::gen_u64(&mut gen);
+                let seeds = diff_seed(t, amount).unwrap_or_else(|| types::gen_vec_u8(&mut gen));
+                write_seed(&corpus_dir, &seeds)?;
+                idx += 1;
+            }
+            Ok(corpus_dir)
+        }
 
         _ => panic!("Unable to generate fuzzing seeds for {:?}", &target),
     }
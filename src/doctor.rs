@@ -0,0 +1,262 @@
+//! Pre-flight checks of the environment the engines need.
+//!
+//! Every check reports what it found and, when something is missing, the command that fixes it.
+//! Checks only needed by engines other than the one being set up are reported as warnings.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::engines::Fuzzer;
+use anyhow::{anyhow, Result};
+
+/// Sibling checkouts `fuzz_targets/Cargo.toml` points at, with whether the build needs them
+const CHECKOUTS: &[(&str, &str, bool)] = &[
+    ("tari", "base_layer/core/Cargo.toml", true),
+    ("tari_utilities", "Cargo.toml", true),
+    // `tari_crypto` comes from crates.io, the checkout is only needed to patch it locally
+    ("tari-crypto", "Cargo.toml", false),
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Collects the outcome of every check
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn record(&mut self, outcome: Outcome, what: &str, fix: &str) {
+        match outcome {
+            Outcome::Pass => println!("\u{2705} {}", what),
+            Outcome::Warn => {
+                self.warnings += 1;
+                println!("\u{26A0}\u{FE0F}  {}\n   fix: {}", what, fix);
+            }
+            Outcome::Fail => {
+                self.failures += 1;
+                println!("\u{274C} {}\n   fix: {}", what, fix);
+            }
+        }
+    }
+
+    /// `Fail` when `required`, `Warn` otherwise
+    fn missing(&mut self, required: bool, what: &str, fix: &str) {
+        let outcome = if required {
+            Outcome::Fail
+        } else {
+            Outcome::Warn
+        };
+        self.record(outcome, what, fix);
+    }
+}
+
+/// Whether `command` runs and exits successfully
+fn probe(command: &mut Command) -> bool {
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// The stdout of `command` if it runs and exits successfully
+fn probe_output(command: &mut Command) -> Option<String> {
+    command
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn check_toolchain(report: &mut Report, engine: Option<Fuzzer>) {
+    if !probe(Command::new("cargo").arg("--version")) {
+        report.record(
+            Outcome::Fail,
+            "cargo not found",
+            "install Rust from https://rustup.rs",
+        );
+        return;
+    }
+    report.record(Outcome::Pass, "cargo found", "");
+
+    // Libfuzzer and Honggfuzz build the targets with `-Z sanitizer=address`
+    let needs_sanitizer = match engine {
+        Some(engine) => engine == Fuzzer::Libfuzzer || engine == Fuzzer::Honggfuzz,
+        None => true,
+    };
+    if probe(Command::new("rustc").args(&["-Z", "sanitizer=address", "--print", "sysroot"])) {
+        report.record(
+            Outcome::Pass,
+            "default toolchain is nightly with AddressSanitizer support",
+            "",
+        );
+    } else {
+        let has_nightly = probe_output(Command::new("rustup").args(&["toolchain", "list"]))
+            .map_or(false, |toolchains| toolchains.contains("nightly"));
+        let fix = if has_nightly {
+            "rustup default nightly (or `rustup override set nightly` in this directory)"
+        } else {
+            "rustup toolchain install nightly && rustup override set nightly"
+        };
+        report.missing(
+            needs_sanitizer,
+            "default toolchain cannot build with `-Z sanitizer=address` (Libfuzzer, Honggfuzz)",
+            fix,
+        );
+    }
+
+    let has_llvm_tools =
+        probe_output(Command::new("rustup").args(&["component", "list", "--installed"]))
+            .map_or(false, |components| components.contains("llvm-tools"));
+    if has_llvm_tools {
+        report.record(Outcome::Pass, "llvm-tools installed", "");
+    } else {
+        report.record(
+            Outcome::Warn,
+            "llvm-tools not installed (coverage reports, `cargo-fuzz coverage`)",
+            "rustup component add llvm-tools-preview",
+        );
+    }
+}
+
+fn check_engine(report: &mut Report, fuzzer: Fuzzer, required: bool) {
+    let (command, args, fix): (&str, &[&str], &str) = match fuzzer {
+        Fuzzer::Afl => ("cargo", &["afl", "--version"], "cargo install afl"),
+        Fuzzer::Honggfuzz => (
+            "cargo",
+            &["hfuzz", "version"],
+            "cargo install honggfuzz --version 0.5.45",
+        ),
+        Fuzzer::Libfuzzer => ("cargo", &["fuzz", "--version"], "cargo install cargo-fuzz"),
+        // Built from their own dependencies
+        Fuzzer::LibAfl | Fuzzer::Inprocess => {
+            report.record(
+                Outcome::Pass,
+                &format!("{} needs no extra tools", fuzzer),
+                "",
+            );
+            return;
+        }
+    };
+    if probe(Command::new(command).args(args)) {
+        report.record(Outcome::Pass, &format!("{} tools found", fuzzer), "");
+    } else {
+        report.missing(
+            required,
+            &format!(
+                "{} tools not found (`{} {}`)",
+                fuzzer,
+                command,
+                args.join(" ")
+            ),
+            fix,
+        );
+    }
+
+    if fuzzer == Fuzzer::Afl {
+        check_core_pattern(report, required);
+    }
+}
+
+/// AFL refuses to start when core dumps are piped to a helper, which would delay crash detection
+fn check_core_pattern(report: &mut Report, required: bool) {
+    let core_pattern = match fs::read_to_string("/proc/sys/kernel/core_pattern") {
+        Ok(core_pattern) => core_pattern,
+        // Not Linux, AFL checks nothing
+        Err(_) => return,
+    };
+    if core_pattern.trim_start().starts_with('|') {
+        report.missing(
+            required,
+            &format!(
+                "core_pattern pipes core dumps to `{}` (Afl)",
+                core_pattern.trim().trim_start_matches('|')
+            ),
+            "echo core | sudo tee /proc/sys/kernel/core_pattern \
+             (or set AFL_I_DONT_CARE_ABOUT_MISSING_CRASHES=1)",
+        );
+    } else {
+        report.record(Outcome::Pass, "core_pattern is fine for Afl", "");
+    }
+}
+
+fn check_checkouts(report: &mut Report) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let parent = root.parent().unwrap_or(root);
+    for &(name, manifest, required) in CHECKOUTS {
+        let checkout = parent.join(name);
+        if checkout.join(manifest).is_file() {
+            report.record(
+                Outcome::Pass,
+                &format!("{} found at {}", name, checkout.display()),
+                "",
+            );
+        } else {
+            report.missing(
+                required,
+                &format!("{} checkout not found at {}", name, checkout.display()),
+                &format!(
+                    "git clone https://github.com/tari-project/{} {}",
+                    name,
+                    checkout.display()
+                ),
+            );
+        }
+    }
+}
+
+/// Check everything `engine` needs, or every engine when `None`, and print how to fix what is
+/// missing. Fails when something required is missing.
+pub fn run_doctor(engine: Option<Fuzzer>) -> Result<()> {
+    let mut report = Report {
+        failures: 0,
+        warnings: 0,
+    };
+    check_toolchain(&mut report, engine);
+    check_checkouts(&mut report);
+    match engine {
+        Some(engine) => check_engine(&mut report, engine, true),
+        None => {
+            for &fuzzer in &[
+                Fuzzer::Libfuzzer,
+                Fuzzer::Afl,
+                Fuzzer::Honggfuzz,
+                Fuzzer::LibAfl,
+                Fuzzer::Inprocess,
+            ] {
+                // Libfuzzer is the default engine
+                check_engine(&mut report, fuzzer, fuzzer == Fuzzer::Libfuzzer);
+            }
+        }
+    }
+    if probe(Command::new("gdb").arg("--version")) {
+        report.record(Outcome::Pass, "gdb found", "");
+    } else {
+        report.record(
+            Outcome::Warn,
+            "gdb not found (triage-hangs)",
+            "apt install gdb",
+        );
+    }
+
+    if report.failures > 0 {
+        return Err(anyhow!(
+            "{} check(s) failed, {} warning(s)",
+            report.failures,
+            report.warnings
+        ));
+    }
+    println!("All required checks passed, {} warning(s)", report.warnings);
+    Ok(())
+}
@@ -17,6 +17,8 @@ arg_enum! {
         Afl,
         Honggfuzz,
         Libfuzzer,
+        LibAfl,
+        Inprocess,
     }
 }
 
@@ -27,6 +29,10 @@ impl Fuzzer {
             Fuzzer::Afl => "fuzzer-afl",
             Fuzzer::Honggfuzz => "fuzzer-honggfuzz",
             Fuzzer::Libfuzzer => "fuzzer-libfuzzer",
+            Fuzzer::LibAfl => "fuzzer-libafl",
+            // Not a package, the in-process engine is built into this crate and only keeps its
+            // corpus and artifacts here
+            Fuzzer::Inprocess => "fuzzer-inprocess",
         }
     }
 
@@ -0,0 +1,351 @@
+//! In-process fuzzing engine on stable Rust.
+//!
+//! Needs no external tooling and no instrumentation: inputs from the proptest corpus are mutated
+//! at the byte level and passed straight to the target function linked from `fuzz_targets`, with
+//! panics caught by `catch_unwind`. Without coverage feedback it only finds shallow bugs, it is
+//! meant for smoke-fuzzing a target before reaching for one of the real engines.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{engines::Fuzzer, runner::RunOptions};
+use anyhow::{anyhow, Context, Result};
+use fuzz_targets::{
+    alloc::{run_with_budget, ALLOC_BUDGET_ENV},
+    target_fn,
+};
+use log::{info, trace, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Mutated inputs are kept below this size
+const MAX_INPUT_LEN: usize = 4096;
+/// Up to this many mutations are stacked on one input
+const MAX_STACKED_MUTATIONS: usize = 4;
+/// Number of mutation operators, see `Mutator::mutate`
+const MUTATIONS: usize = 11;
+/// How often progress is logged
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Boundary values that tend to hit edge cases in length and arithmetic checks, as in AFL
+const INTERESTING_8: &[i8] = &[-128, -1, 0, 1, 16, 32, 64, 100, 127];
+const INTERESTING_16: &[i16] = &[-32768, -129, 128, 255, 256, 512, 1000, 1024, 4096, 32767];
+const INTERESTING_32: &[i32] = &[
+    i32::min_value(),
+    -100_663_046,
+    -32769,
+    32768,
+    65535,
+    65536,
+    100_663_045,
+    i32::max_value(),
+];
+const INTERESTING_64: &[u64] = &[
+    0,
+    1,
+    u32::max_value() as u64,
+    u32::max_value() as u64 + 1,
+    i64::max_value() as u64,
+    u64::max_value(),
+];
+
+/// Byte-level mutations on corpus entries
+struct Mutator {
+    rng: StdRng,
+}
+
+impl Mutator {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Overwrite `bytes` at a random offset, growing the input if it is too short
+    fn overwrite(&mut self, input: &mut Vec<u8>, bytes: &[u8]) {
+        if input.len() < bytes.len() {
+            input.resize(bytes.len(), 0);
+        }
+        let offset = self.rng.gen_range(0, input.len() - bytes.len() + 1);
+        input[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A random range within `len`, `None` when empty
+    fn range(&mut self, len: usize) -> Option<(usize, usize)> {
+        if len == 0 {
+            return None;
+        }
+        let start = self.rng.gen_range(0, len);
+        let end = self.rng.gen_range(start, len) + 1;
+        Some((start, end))
+    }
+
+    fn mutate_once(&mut self, input: &mut Vec<u8>, corpus: &[Vec<u8>]) {
+        let mutation = self.rng.gen_range(0, MUTATIONS);
+        self.mutate(mutation, input, corpus);
+    }
+
+    /// Apply operator `mutation`. Those that change single bytes insert random bytes into an empty
+    /// input instead.
+    fn mutate(&mut self, mutation: usize, input: &mut Vec<u8>, corpus: &[Vec<u8>]) {
+        let len = input.len();
+        match mutation {
+            // Flip a bit
+            0 if len > 0 => {
+                let pos = self.rng.gen_range(0, len);
+                input[pos] ^= 1 << self.rng.gen_range(0, 8);
+            }
+            // Set a random byte
+            1 if len > 0 => {
+                let pos = self.rng.gen_range(0, len);
+                input[pos] = self.rng.gen();
+            }
+            // Add or subtract a small value
+            2 if len > 0 => {
+                let pos = self.rng.gen_range(0, len);
+                let delta = self.rng.gen_range(1, 36);
+                input[pos] = if self.rng.gen() {
+                    input[pos].wrapping_add(delta)
+                } else {
+                    input[pos].wrapping_sub(delta)
+                };
+            }
+            3 => {
+                let value = INTERESTING_8[self.rng.gen_range(0, INTERESTING_8.len())];
+                self.overwrite(input, &value.to_le_bytes());
+            }
+            4 => {
+                let value = INTERESTING_16[self.rng.gen_range(0, INTERESTING_16.len())];
+                let bytes = if self.rng.gen() {
+                    value.to_le_bytes()
+                } else {
+                    value.to_be_bytes()
+                };
+                self.overwrite(input, &bytes);
+            }
+            5 => {
+                let value = INTERESTING_32[self.rng.gen_range(0, INTERESTING_32.len())];
+                let bytes = if self.rng.gen() {
+                    value.to_le_bytes()
+                } else {
+                    value.to_be_bytes()
+                };
+                self.overwrite(input, &bytes);
+            }
+            6 => {
+                let value = INTERESTING_64[self.rng.gen_range(0, INTERESTING_64.len())];
+                let bytes = if self.rng.gen() {
+                    value.to_le_bytes()
+                } else {
+                    value.to_be_bytes()
+                };
+                self.overwrite(input, &bytes);
+            }
+            // Delete a range
+            7 => {
+                if let Some((start, end)) = self.range(len) {
+                    input.drain(start..end);
+                }
+            }
+            // Copy a range over another part of the input
+            8 => {
+                if let Some((start, end)) = self.range(len) {
+                    let chunk = input[start..end].to_vec();
+                    self.overwrite(input, &chunk);
+                }
+            }
+            // Splice: the head of this input with the tail of another corpus entry
+            9 => {
+                let other = &corpus[self.rng.gen_range(0, corpus.len())];
+                let head = self.rng.gen_range(0, len + 1);
+                let tail = self.rng.gen_range(0, other.len() + 1);
+                input.truncate(head);
+                input.extend_from_slice(&other[tail..]);
+            }
+            // Insert random bytes
+            _ => {
+                let pos = self.rng.gen_range(0, len + 1);
+                let count = self.rng.gen_range(1, 17);
+                let bytes: Vec<u8> = (0..count).map(|_| self.rng.gen()).collect();
+                input.splice(pos..pos, bytes);
+            }
+        }
+    }
+
+    /// A mutated copy of a random corpus entry
+    fn next_input(&mut self, corpus: &[Vec<u8>]) -> Vec<u8> {
+        let mut input = corpus[self.rng.gen_range(0, corpus.len())].clone();
+        for _ in 0..self.rng.gen_range(1, MAX_STACKED_MUTATIONS + 1) {
+            self.mutate_once(&mut input, corpus);
+        }
+        input.truncate(MAX_INPUT_LEN);
+        input
+    }
+}
+
+fn load_corpus(corpus_dir: &Path) -> Result<Vec<Vec<u8>>> {
+    let mut corpus = Vec::new();
+    for entry in fs::read_dir(corpus_dir).context(format!(
+        "unable to read corpus dir {}",
+        corpus_dir.display()
+    ))? {
+        let path = entry?.path();
+        if path.is_file() {
+            corpus.push(fs::read(&path).context(format!("unable to read {}", path.display()))?);
+        }
+    }
+    // Mutations can grow an empty input into anything
+    if corpus.is_empty() {
+        corpus.push(Vec::new());
+    }
+    Ok(corpus)
+}
+
+/// Save `input` as `{prefix}-{hash}` in `dir`
+fn save_input(dir: &Path, prefix: &str, input: &[u8]) -> Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    fs::create_dir_all(dir).context(format!("unable to create {}", dir.display()))?;
+    let path = dir.join(format!("{}-{:016x}", prefix, hasher.finish()));
+    fs::write(&path, input).context(format!("unable to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Run one target fuzz test in-process
+pub fn run_inprocess(target: &str, fuzzer: Fuzzer, options: &RunOptions) -> Result<()> {
+    trace!("Getting ready to start fuzzing in-process");
+    let fuzz_target = target_fn(target)
+        .ok_or_else(|| anyhow!("{} is not built into the in-process engine", target))?;
+    let artifact_dir = options.artifact_dir(fuzzer, target)?;
+    let corpus_dir = options.corpus(fuzzer, target)?;
+    let corpus = load_corpus(&corpus_dir)?;
+    env::set_var(ALLOC_BUDGET_ENV, options.alloc_budget_mb.to_string());
+
+    let seed: u64 = rand::random();
+    info!(
+        "Fuzzing {} in-process from {} corpus entries, seed {}",
+        target,
+        corpus.len(),
+        seed
+    );
+    let mut mutator = Mutator::new(seed);
+    let timeout = Duration::from_millis(options.timeout_ms as u64);
+
+    // Keep the panic message for the report instead of printing one per caught panic
+    let last_panic = Arc::new(Mutex::new(String::new()));
+    let default_hook = panic::take_hook();
+    {
+        let last_panic = last_panic.clone();
+        panic::set_hook(Box::new(move |info| {
+            if let Ok(mut last_panic) = last_panic.lock() {
+                *last_panic = info.to_string();
+            }
+        }));
+    }
+
+    let started = Instant::now();
+    let mut last_status = started;
+    let mut execs: usize = 0;
+    let result = loop {
+        if options.runs > 0 && execs >= options.runs {
+            break Ok(());
+        }
+        let input = mutator.next_input(&corpus);
+        let start = Instant::now();
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_with_budget(&input, || fuzz_target(&input))
+        }));
+        let elapsed = start.elapsed();
+        execs += 1;
+
+        if outcome.is_err() {
+            let path = save_input(&artifact_dir, "crash", &input)?;
+            let message = last_panic.lock().map(|m| m.clone()).unwrap_or_default();
+            break Err(anyhow!(
+                "{} crashed after {} runs: {}\nReproducer saved to {}",
+                target,
+                execs,
+                message,
+                path.display()
+            ));
+        }
+        if options.timeout_ms > 0 && elapsed > timeout {
+            let path = save_input(&options.hangs_dir(fuzzer, target), "slow", &input)?;
+            warn!(
+                "Input took {} ms, saved to {}",
+                elapsed.as_millis(),
+                path.display()
+            );
+        }
+        if last_status.elapsed() >= STATUS_INTERVAL {
+            last_status = Instant::now();
+            let secs = started.elapsed().as_secs().max(1) as usize;
+            info!("#{} runs, {} exec/s", execs, execs / secs);
+        }
+    };
+
+    panic::set_hook(default_hook);
+    if result.is_ok() {
+        info!("Done {} runs without a crash", execs);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs at the edges of the length range
+    fn edge_inputs() -> Vec<Vec<u8>> {
+        vec![Vec::new(), vec![0x5a], vec![0xa5; MAX_INPUT_LEN]]
+    }
+
+    #[test]
+    fn each_mutation_handles_edge_lengths() {
+        let corpus = edge_inputs();
+        for mutation in 0..MUTATIONS {
+            for seed in 0..64 {
+                let mut mutator = Mutator::new(seed);
+                for input in &corpus {
+                    let mut mutated = input.clone();
+                    mutator.mutate(mutation, &mut mutated, &corpus);
+                    // Splicing appends up to a whole corpus entry, the others at most 16
+                    // inserted bytes or 8 overwritten past the end
+                    let growth = if mutation == 9 { MAX_INPUT_LEN } else { 16 };
+                    assert!(
+                        mutated.len() <= input.len() + growth,
+                        "mutation {} with seed {} grew {} bytes to {}",
+                        mutation,
+                        seed,
+                        input.len(),
+                        mutated.len()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn next_input_stays_within_max_len() {
+        let corpus = edge_inputs();
+        let mut mutator = Mutator::new(0);
+        for _ in 0..10_000 {
+            assert!(mutator.next_input(&corpus).len() <= MAX_INPUT_LEN);
+        }
+    }
+
+    #[test]
+    fn mutations_are_deterministic_per_seed() {
+        let corpus = edge_inputs();
+        let (mut a, mut b) = (Mutator::new(42), Mutator::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_input(&corpus), b.next_input(&corpus));
+        }
+    }
+}
@@ -1,8 +1,12 @@
 pub use engines::Fuzzer;
 pub use errors::{Result, TariError};
 
+pub mod build;
 pub mod corpus;
+pub mod doctor;
 pub mod engines;
 pub mod errors;
+pub mod inprocess;
 pub mod runner;
+pub mod triage;
 pub mod types;
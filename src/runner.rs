@@ -18,27 +18,280 @@ use std::{
     env, fs,
     io::Write,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
 };
 
-use crate::{corpus::gen_corpus, engines::Fuzzer};
+use crate::{
+    build::{build_target, TARGET_PLATFORM},
+    corpus::{create_corpus_dir, gen_corpus, gen_corpus_in},
+    engines::Fuzzer,
+};
 use anyhow::{anyhow, Context, Result};
 use cargo_metadata::MetadataCommand;
+use fuzz_targets::{alloc::ALLOC_BUDGET_ENV, DIFF_TARGETS, TARGETS};
 use lazy_static::lazy_static;
 use log::{info, trace};
+use structopt::clap::arg_enum;
 
 lazy_static! {
-    static ref WORKSPACE_ROOT: PathBuf = MetadataCommand::new()
+    pub(crate) static ref WORKSPACE_ROOT: PathBuf = MetadataCommand::new()
         .no_deps()
         .exec()
         .unwrap()
         .workspace_root;
 }
 
+/// Options shared by all engines for a fuzzing run
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Number of seeds to generate for the corpus
+    pub seeds: usize,
+    /// Memory limit for the target process in MB, 0 for none
+    pub rss_limit_mb: usize,
+    /// Memory a single input may hold at once in MB, enforced by the harness, 0 for none
+    pub alloc_budget_mb: usize,
+    /// Time a single input may take in milliseconds, 0 for the engine's default
+    pub timeout_ms: usize,
+    /// Number of inputs to run before stopping, 0 to run until a crash
+    pub runs: usize,
+    /// Number of cores to fuzz on, 0 for all (LibAFL only)
+    pub cores: usize,
+    /// Corpus directory to use instead of `corpus-{target}` in the engine's directory
+    pub corpus_dir: Option<PathBuf>,
+    /// Directory for crashes and other engine output instead of `artifact-{target}` in the
+    /// engine's directory
+    pub artifacts_dir: Option<PathBuf>,
+}
+
+impl RunOptions {
+    /// The timeout rounded up to whole seconds, for engines that take seconds
+    pub(crate) fn timeout_secs(&self) -> usize {
+        (self.timeout_ms + 999) / 1000
+    }
+
+    /// The corpus directory of `target`, with `seeds` seeds generated into it.
+    ///
+    /// Engines run from their own directory, so a directory given on the command line is made
+    /// absolute before they see it.
+    pub(crate) fn corpus(&self, fuzzer: Fuzzer, target: &str) -> Result<PathBuf> {
+        match &self.corpus_dir {
+            Some(corpus_dir) => {
+                let corpus_dir = gen_corpus_in(target, corpus_dir.clone(), self.seeds)?;
+                fs::canonicalize(&corpus_dir)
+                    .context(format!("unable to resolve {}", corpus_dir.display()))
+            }
+            None => gen_corpus(target, fuzzer, self.seeds),
+        }
+    }
+
+    /// The artifact directory of `target`, created if needed and absolute like `corpus`
+    pub(crate) fn artifact_dir(&self, fuzzer: Fuzzer, target: &str) -> Result<PathBuf> {
+        match &self.artifacts_dir {
+            Some(artifacts_dir) => {
+                fs::create_dir_all(artifacts_dir).context(format!(
+                    "unable to create artifact dir {}",
+                    artifacts_dir.display()
+                ))?;
+                let artifacts_dir = fs::canonicalize(artifacts_dir)
+                    .context(format!("unable to resolve {}", artifacts_dir.display()))?;
+                info!("Artifact directory: {:?}", artifacts_dir);
+                Ok(artifacts_dir)
+            }
+            None => create_artifact_dir(fuzzer.directory(), target),
+        }
+    }
+
+    /// The hangs directory of `target`, see `hangs_dir`
+    pub(crate) fn hangs_dir(&self, fuzzer: Fuzzer, target: &str) -> PathBuf {
+        hangs_dir(fuzzer, target, self.artifacts_dir.as_deref())
+    }
+}
+
+/// Where the source of `target` goes, relative to the engine's directory. Libfuzzer uses
+/// cargo-fuzz's layout.
+fn target_source_path(fuzzer: Fuzzer, target: &str) -> String {
+    match fuzzer {
+        Fuzzer::Libfuzzer => format!("fuzz_targets/{}.rs", target),
+        _ => format!("src/bin/{}.rs", target),
+    }
+}
+
+/// Start of the generated part of the cargo-fuzz manifest, everything after it is rewritten
+const CARGO_FUZZ_BINS_MARKER: &str =
+    "# Targets below are generated from `fuzz_targets::TARGETS` by `cargo run cargo-fuzz sync`";
+
+/// Write the source of every target in cargo-fuzz's layout and give each a `[[bin]]` entry in
+/// the Libfuzzer manifest, so `cargo fuzz` and OSS-Fuzz style build scripts see all targets.
+pub fn sync_cargo_fuzz_layout() -> Result<()> {
+    trace!("Syncing the cargo-fuzz layout with the registered targets");
+    let fuzzer = Fuzzer::Libfuzzer;
+    let sources_dir = fuzzer.directory().join("fuzz_targets");
+    fs::create_dir_all(&sources_dir)
+        .context(format!("unable to create {}", sources_dir.display()))?;
+    for target in TARGETS.iter() {
+        write_fuzz_target_source_file(&fuzzer, target)?;
+    }
+
+    let manifest_path = fuzzer.directory().join("Cargo.toml");
+    let current = fs::read_to_string(&manifest_path)
+        .context(format!("Error reading {}", manifest_path.display()))?;
+    let manifest = cargo_fuzz_manifest(&current);
+    if manifest != current {
+        fs::write(&manifest_path, manifest)
+            .context(format!("Error writing {}", manifest_path.display()))?;
+        info!("Updated the fuzz targets in {:?}", &manifest_path);
+    }
+    Ok(())
+}
+
+/// `current` with everything after `CARGO_FUZZ_BINS_MARKER` replaced by a `[[bin]]` entry per
+/// target in `TARGETS`
+fn cargo_fuzz_manifest(current: &str) -> String {
+    let fuzzer = Fuzzer::Libfuzzer;
+    let head = current
+        .split(CARGO_FUZZ_BINS_MARKER)
+        .next()
+        .unwrap_or_default();
+    let mut manifest = format!("{}\n\n{}\n", head.trim_end(), CARGO_FUZZ_BINS_MARKER);
+    for target in TARGETS.iter() {
+        manifest.push_str(&format!(
+            "\n[[bin]]\nname = \"{}\"\npath = \"{}\"\ntest = false\ndoc = false\n",
+            target,
+            target_source_path(fuzzer, target)
+        ));
+        if DIFF_TARGETS.contains(target) {
+            manifest.push_str("required-features = [\"differential\"]\n");
+        }
+    }
+    manifest
+}
+
+arg_enum! {
+    /// `cargo fuzz` subcommands, plus `sync` to only update the layout
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum CargoFuzzCommand {
+        Sync,
+        Build,
+        Run,
+        Cmin,
+        Tmin,
+        Coverage,
+    }
+}
+
+/// Delegate to `cargo fuzz` on the Libfuzzer package.
+///
+/// `run`, `cmin` and `coverage` get the target's corpus directory, `args` are passed on as they
+/// are, e.g. the crash to minimize for `tmin` or `-- -max_total_time=60` for `run`.
+pub fn run_cargo_fuzz(
+    command: CargoFuzzCommand,
+    target: Option<&str>,
+    args: &[String],
+) -> Result<()> {
+    sync_cargo_fuzz_layout()?;
+    if command == CargoFuzzCommand::Sync {
+        return Ok(());
+    }
+    pre_check(
+        Command::new("cargo").args(&["fuzz", "--version"]),
+        "cargo install cargo-fuzz",
+    )?;
+
+    let fuzzer = Fuzzer::Libfuzzer;
+    let subcommand = command.to_string().to_lowercase();
+    let mut cargo_fuzz = Command::new("cargo");
+    cargo_fuzz
+        .args(&["fuzz", &subcommand, "--fuzz-dir"])
+        .arg(fuzzer.directory());
+    match target {
+        Some(target) => {
+            cargo_fuzz.args(target_features(target)).arg(target);
+            match command {
+                CargoFuzzCommand::Run | CargoFuzzCommand::Cmin | CargoFuzzCommand::Coverage => {
+                    cargo_fuzz.arg(create_corpus_dir(fuzzer.directory(), target)?);
+                }
+                _ => {}
+            }
+        }
+        None if command != CargoFuzzCommand::Build => {
+            return Err(anyhow!("cargo fuzz {} needs a target", subcommand));
+        }
+        None => {}
+    }
+    let status = cargo_fuzz
+        .args(args)
+        .current_dir(fuzzer.directory())
+        .spawn()
+        .context(format!("Failed to run cargo fuzz {}", subcommand))?
+        .wait()
+        .context(format!("Failed to wait for cargo fuzz {}", subcommand))?;
+
+    if !status.success() {
+        return Err(anyhow!(
+            "cargo fuzz {} exited with code {:?}",
+            subcommand,
+            status.code()
+        ));
+    }
+    Ok(())
+}
+
+/// Run one target fuzz test with LibAFL
+pub fn run_libafl(target: &str, fuzzer: Fuzzer, options: &RunOptions) -> Result<()> {
+    trace!("Getting ready to start fuzzing with LibAFL");
+    let artifact_dir = options.artifact_dir(fuzzer, target)?;
+    let corpus_dir = options.corpus(fuzzer, target)?;
+    let fuzz_bin = build_target(target, fuzzer, false)?;
+    // Timeouts are saved here by the binary itself, which runs from the engine's directory
+    let hangs_dir = options.hangs_dir(fuzzer, target);
+    fs::create_dir_all(&hangs_dir).context(format!(
+        "unable to create hangs dir {}",
+        hangs_dir.display()
+    ))?;
+    let hangs_dir = fs::canonicalize(&hangs_dir)
+        .context(format!("unable to resolve {}", hangs_dir.display()))?;
+
+    let cores = match options.cores {
+        0 => "all".to_string(),
+        cores => format!("0-{}", cores - 1),
+    };
+
+    let mut command = Command::new(&fuzz_bin);
+    command
+        .arg(&corpus_dir)
+        .arg(&artifact_dir)
+        .arg(&hangs_dir)
+        .env("TARI_LIBAFL_CORES", &cores)
+        .env("TARI_LIBAFL_RUNS", options.runs.to_string())
+        // 0 disables the timeout instead of falling back to the template's default
+        .env("TARI_LIBAFL_TIMEOUT_MS", options.timeout_ms.to_string())
+        .env(ALLOC_BUDGET_ENV, options.alloc_budget_mb.to_string());
+    let fuzzer_bin = command
+        .current_dir(fuzzer.directory())
+        .spawn()
+        .context(format!("Failed to run {}", fuzzer))?
+        .wait()
+        .context(format!("Failed to wait {}", fuzzer))?;
+
+    if !fuzzer_bin.success() {
+        return Err(anyhow!(
+            "{} exited with code {:?}",
+            fuzzer,
+            fuzzer_bin.code()
+        ));
+    }
+
+    Ok(())
+}
+
 /// Write the fuzz target source file from corresponding template file.
 ///
 /// `target` must be a valid target.
-fn write_fuzz_target_source_file(fuzzer: &Fuzzer, target: &str) -> Result<()> {
+pub(crate) fn write_fuzz_target_source_file(fuzzer: &Fuzzer, target: &str) -> Result<()> {
     trace!("Writing target fuzz test to file");
     let template_file_path = fuzzer.directory().join("template.rs");
     let template = fs::read_to_string(&template_file_path).context(format!(
@@ -46,7 +299,15 @@ fn write_fuzz_target_source_file(fuzzer: &Fuzzer, target: &str) -> Result<()> {
         template_file_path.display()
     ))?;
 
-    let target_file_path = fuzzer.directory().join(&format!("src/bin/{}.rs", target));
+    let target_file_path = fuzzer.directory().join(target_source_path(*fuzzer, target));
+    let source = template.replace("__FUZZ_CLI_TARGET__", &target).replace(
+        "__FUZZ_GENERATE_COMMENT__",
+        "NOTE: AUTO GENERATED FROM `template.rs`",
+    );
+    // Rewriting an unchanged file would make cargo rebuild the target
+    if fs::read_to_string(&target_file_path).ok().as_deref() == Some(source.as_str()) {
+        return Ok(());
+    }
     info!("Target file written to {:?}", &target_file_path);
     let mut file = fs::OpenOptions::new()
         .write(true)
@@ -57,15 +318,11 @@ fn write_fuzz_target_source_file(fuzzer: &Fuzzer, target: &str) -> Result<()> {
             "Error writing fuzz target source file {}",
             target_file_path.display()
         ))?;
-    let source = template.replace("__FUZZ_CLI_TARGET__", &target).replace(
-        "__FUZZ_GENERATE_COMMENT__",
-        "NOTE: AUTO GENERATED FROM `template.rs`",
-    );
     file.write_all(source.as_bytes())?;
     Ok(())
 }
 
-fn create_artifact_dir(base: impl AsRef<Path>, target: &str) -> Result<PathBuf> {
+pub(crate) fn create_artifact_dir(base: impl AsRef<Path>, target: &str) -> Result<PathBuf> {
     trace!("Creating artifact directory for fuzzer output");
     let base = base.as_ref();
     let artifact_dir = base.join(&format!("artifact-{}", target));
@@ -78,12 +335,160 @@ fn create_artifact_dir(base: impl AsRef<Path>, target: &str) -> Result<PathBuf>
     Ok(artifact_dir)
 }
 
-fn pre_check(command: &mut Command, hint: &str) -> Result<()> {
+/// Directory the inputs that timed out are collected in: inside `artifacts_dir` when one was
+/// given with `--artifacts-dir`, next to the artifacts in the engine's directory otherwise
+pub fn hangs_dir(fuzzer: Fuzzer, target: &str, artifacts_dir: Option<&Path>) -> PathBuf {
+    let name = format!("hangs-{}", target);
+    match artifacts_dir {
+        Some(artifacts_dir) => artifacts_dir.join(name),
+        None => fuzzer.directory().join(name),
+    }
+}
+
+/// Where an engine saves its timeouts, with the test picking them out by file name
+type HangCandidates = Vec<(PathBuf, fn(&str) -> bool)>;
+
+/// Move the inputs each engine saved for timeouts into the hangs dir, so hangs are kept apart from
+/// crashes whatever engine found them.
+///
+/// Libfuzzer writes `timeout-*` files and Honggfuzz names timeouts after the `SIGVTALRM` it kills
+/// them with, both next to the crashes in the artifact dir, so they are moved out of it. AFL keeps
+/// a `hangs` folder in its output directory, which is left as it is and copied.
+fn collect_hangs(fuzzer: Fuzzer, artifact_dir: &Path, hangs_dir: &Path) -> Result<()> {
+    let candidates: HangCandidates = match fuzzer {
+        Fuzzer::Libfuzzer => vec![(artifact_dir.to_path_buf(), |name| {
+            name.starts_with("timeout-")
+        })],
+        Fuzzer::Afl => vec![
+            (artifact_dir.join("hangs"), |name| name.starts_with("id:")),
+            (artifact_dir.join("default/hangs"), |name| {
+                name.starts_with("id:")
+            }),
+        ],
+        Fuzzer::Honggfuzz => vec![(artifact_dir.to_path_buf(), |name| {
+            name.contains("SIGVTALRM")
+        })],
+        // Save their hangs to the hangs dir themselves
+        Fuzzer::LibAfl | Fuzzer::Inprocess => Vec::new(),
+    };
+
+    let mut count = 0;
+    for (dir, is_hang) in candidates {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if is_hang(name) => name.replace(':', "_"),
+                _ => continue,
+            };
+            fs::create_dir_all(hangs_dir).context(format!(
+                "unable to create hangs dir {}",
+                hangs_dir.display()
+            ))?;
+            let hang = hangs_dir.join(&name);
+            if dir != artifact_dir {
+                fs::copy(&path, &hang)
+                    .context(format!("unable to copy hang {}", path.display()))?;
+            } else if fs::rename(&path, &hang).is_err() {
+                // Across file systems
+                fs::copy(&path, &hang)
+                    .and_then(|_| fs::remove_file(&path))
+                    .context(format!("unable to move hang {}", path.display()))?;
+            }
+            count += 1;
+        }
+    }
+    if count > 0 {
+        info!("{} hang(s) saved to {:?}", count, hangs_dir);
+    }
+    Ok(())
+}
+
+/// Set once Ctrl-C interrupted a campaign, see `catch_interrupts`
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Keep the runner alive when Ctrl-C interrupts a campaign. The engine, in the same process group,
+/// gets the SIGINT as well and stops, and the runner goes on to collect what it saved.
+fn catch_interrupts() -> Result<()> {
+    static HANDLER: Once = Once::new();
+    let mut result = Ok(());
+    HANDLER.call_once(|| {
+        result = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst));
+    });
+    result.context("unable to handle Ctrl-C")
+}
+
+/// Wait for the engine to stop and collect its hangs, also when it was interrupted: stopping
+/// with Ctrl-C is how campaigns without a run limit end, so the exit status is not an error then
+fn wait_for_engine(
+    fuzzer: Fuzzer,
+    mut engine: Child,
+    artifact_dir: &Path,
+    hangs_dir: &Path,
+) -> Result<()> {
+    let status = engine
+        .wait()
+        .context(format!("Failed to wait {}", fuzzer))?;
+    collect_hangs(fuzzer, artifact_dir, hangs_dir)?;
+    if !status.success() && !INTERRUPTED.load(Ordering::SeqCst) {
+        return Err(anyhow!("{} exited with code {:?}", fuzzer, status.code()));
+    }
+    Ok(())
+}
+
+/// The binary each engine builds for `target`; Honggfuzz's is the debug build from
+/// `cargo hfuzz build-debug`, which runs inputs outside of the fuzzer. `None` for the in-process
+/// engine, which runs targets inside this crate's own binary.
+pub(crate) fn engine_binary(fuzzer: Fuzzer, target: &str) -> Option<PathBuf> {
+    let binary = match fuzzer {
+        Fuzzer::Afl => WORKSPACE_ROOT.join("target/debug").join(target),
+        Fuzzer::Honggfuzz => fuzzer
+            .directory()
+            .join("hfuzz_target")
+            .join(&*TARGET_PLATFORM)
+            .join("debug")
+            .join(target),
+        Fuzzer::Libfuzzer => fuzzer
+            .directory()
+            .join("target")
+            .join(&*TARGET_PLATFORM)
+            .join("debug")
+            .join(target),
+        Fuzzer::LibAfl => fuzzer.directory().join("target/release").join(target),
+        Fuzzer::Inprocess => return None,
+    };
+    Some(binary)
+}
+
+/// Cargo features the engine package needs to build `target`.
+///
+/// Differential targets are compiled against the baseline revision as well, which only happens
+/// with the `differential` feature.
+pub(crate) fn target_features(target: &str) -> Vec<&'static str> {
+    if DIFF_TARGETS.contains(&target) {
+        vec!["--features", "differential"]
+    } else {
+        Vec::new()
+    }
+}
+
+pub(crate) fn pre_check(command: &mut Command, hint: &str) -> Result<()> {
     let check = command
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .status()
-        .unwrap();
+        .map_err(|e| {
+            anyhow!(
+                "Pre-checking for fuzzing failed, {:?} could not be run ({}). Consider run `{}` \
+                 before fuzzing, `cargo run doctor` lists everything that is missing.",
+                command,
+                e,
+                hint
+            )
+        })?;
     if !check.success() {
         Err(anyhow!(
             "Pre-checking for fuzzing failed. Consider run `{}` before fuzzing.",
@@ -95,206 +500,186 @@ fn pre_check(command: &mut Command, hint: &str) -> Result<()> {
 }
 
 /// Run one target fuzz test using AFL
-pub fn run_afl(target: &str, fuzzer: Fuzzer, seeds: usize) -> Result<()> {
+pub fn run_afl(target: &str, fuzzer: Fuzzer, options: &RunOptions) -> Result<()> {
     trace!("Getting ready to start fuzzing with AFL");
-    write_fuzz_target_source_file(&fuzzer, &target)?;
-    let artifact_dir = create_artifact_dir(fuzzer.directory(), target)?;
-    let corpus_dir = gen_corpus(&target, fuzzer, seeds)?;
-
-    pre_check(
-        Command::new("cargo").args(&["afl", "--version"]),
-        "cargo install afl",
-    )?;
-
-    // 1. cargo afl build (in fuzzer-afl directory)
-    let fuzzer_build = Command::new("cargo")
-        .args(&["afl", "build", "--bin", target])
-        .current_dir(fuzzer.directory())
-        .spawn()
-        .context(format!("Failed to build {}", fuzzer))?
-        .wait()
-        .context(format!("Failed to complete building {}", fuzzer))?;
-
-    if !fuzzer_build.success() {
-        return Err(anyhow!(
-            "error building afl instrumented binary, exit code {:?}",
-            fuzzer_build.code()
-        ));
+    let artifact_dir = options.artifact_dir(fuzzer, target)?;
+    let corpus_dir = options.corpus(fuzzer, target)?;
+
+    // 1. cargo afl build (in fuzzer-afl directory), unless nothing changed
+    let instrumented_bin = build_target(target, fuzzer, false)?;
+
+    // 2. cargo afl fuzz -i {seed_dir} -o {corpus_dir} -m {rss_limit} target/debug/{instrumented_binary}
+    let memory_limit = match options.rss_limit_mb {
+        0 => "none".to_string(),
+        mb => mb.to_string(),
+    };
+    let mut afl_args = vec!["-m".to_string(), memory_limit];
+    if options.timeout_ms > 0 {
+        afl_args.push("-t".to_string());
+        afl_args.push(options.timeout_ms.to_string());
     }
-
-    // 2. cargo afl fuzz -i {seed_dir} -o {corpus_dir} target/debug/{instrumented_binary}
-    let instrumented_bin = WORKSPACE_ROOT.join("target/debug").join(target);
-    let fuzzer_bin = Command::new("cargo")
+    catch_interrupts()?;
+    let engine = Command::new("cargo")
         .args(&["afl", "fuzz"])
         .arg("-i")
         .arg(&corpus_dir)
         .arg("-o")
         .arg(&artifact_dir)
+        .args(&afl_args)
         .arg(&instrumented_bin)
+        .env(ALLOC_BUDGET_ENV, options.alloc_budget_mb.to_string())
         .current_dir(fuzzer.directory())
         .spawn()
-        .context(format!("Failed to run {}", fuzzer))?
-        .wait()
-        .context(format!("Failed to wait {}", fuzzer))?;
-
-    if !fuzzer_bin.success() {
-        return Err(anyhow!(
-            "{} exited with code {:?}",
-            fuzzer,
-            fuzzer_bin.code()
-        ));
-    }
-
-    Ok(())
+        .context(format!("Failed to run {}", fuzzer))?;
+
+    wait_for_engine(
+        fuzzer,
+        engine,
+        &artifact_dir,
+        &options.hangs_dir(fuzzer, target),
+    )
 }
 
 /// Run one target fuzz test using Honggfuzz
-pub fn run_honggfuzz(target: &str, fuzzer: Fuzzer, seeds: usize) -> Result<()> {
+pub fn run_honggfuzz(target: &str, fuzzer: Fuzzer, options: &RunOptions) -> Result<()> {
     trace!("Getting ready to start fuzzing with Honggfuzz");
-    write_fuzz_target_source_file(&fuzzer, &target)?;
-    let _artifact_dir = create_artifact_dir(fuzzer.directory(), target)?;
-    let corpus_dir = gen_corpus(&target, fuzzer, seeds)?;
-    pre_check(
-        Command::new("cargo").args(&["hfuzz", "version"]),
-        "cargo install honggfuzz --version 0.5.45",
-    )?;
-
-    let mut rust_flags = env::var("RUSTFLAGS").unwrap_or_default();
-    rust_flags.push_str(" -Z sanitizer=address");
-
-    let hfuzz_args = format!(
-        "-f {} --exit_upon_crash {}",
-        corpus_dir.to_string_lossy(),
-        env::var("HFUZZ_RUN_ARGS").unwrap_or_default()
-    );
-
-    let fuzzer_bin = Command::new("cargo")
-        .args(&["hfuzz", "run", target])
-        .env("RUSTFLAGS", &rust_flags)
-        .env("HFUZZ_RUN_ARGS", &hfuzz_args)
-        .current_dir(fuzzer.directory())
-        .spawn()
-        .context(format!("Failed to run {}", fuzzer))?
-        .wait()
-        .context(format!("Failed to wait {}", fuzzer))?;
-
-    if !fuzzer_bin.success() {
+    let artifact_dir = options.artifact_dir(fuzzer, target)?;
+    let corpus_dir = options.corpus(fuzzer, target)?;
+    let fuzz_bin = build_target(target, fuzzer, false)?;
+    // Put there by `cargo hfuzz build`. Running it directly, the way `cargo hfuzz run` does,
+    // keeps cargo out of the way of the cached binary.
+    let honggfuzz = fuzzer.directory().join("hfuzz_target").join("honggfuzz");
+    if !honggfuzz.exists() {
         return Err(anyhow!(
-            "{} exited with code {:?}",
-            fuzzer,
-            fuzzer_bin.code()
+            "{} not found, run `cargo hfuzz build` in {}",
+            honggfuzz.display(),
+            fuzzer.directory().display()
         ));
     }
 
-    Ok(())
+    // Honggfuzz reports timeouts as crashes with `--tmout_sigvtalrm`, so with a timeout it must
+    // not stop on the first crash: the first slow input would end the campaign
+    let timeout_args = if options.timeout_ms > 0 {
+        vec![
+            "--timeout".to_string(),
+            options.timeout_secs().to_string(),
+            "--tmout_sigvtalrm".to_string(),
+        ]
+    } else {
+        vec!["--exit_upon_crash".to_string()]
+    };
+    let user_args = env::var("HFUZZ_RUN_ARGS").unwrap_or_default();
+
+    // The target is built with ASan, which reserves terabytes of address space, so the limit
+    // applies to the resident set (`--rlimit_as` would stop every run from starting)
+    catch_interrupts()?;
+    let engine = Command::new(&honggfuzz)
+        .arg("-f")
+        .arg(&corpus_dir)
+        .arg("-W")
+        .arg(&artifact_dir)
+        .arg("--crashdir")
+        .arg(&artifact_dir)
+        .arg("-P")
+        .args(&["--rlimit_rss", &options.rss_limit_mb.to_string()])
+        .args(&["--iterations", &options.runs.to_string()])
+        .args(&timeout_args)
+        .args(user_args.split_whitespace())
+        .arg("--")
+        .arg(&fuzz_bin)
+        .env(ALLOC_BUDGET_ENV, options.alloc_budget_mb.to_string())
+        .current_dir(fuzzer.directory())
+        .spawn()
+        .context(format!("Failed to run {}", fuzzer))?;
+
+    wait_for_engine(
+        fuzzer,
+        engine,
+        &artifact_dir,
+        &options.hangs_dir(fuzzer, target),
+    )
 }
 
 /// Run one target fuzz test using Libfuzzer
-pub fn run_libfuzzer(target: &str, fuzzer: Fuzzer, seeds: usize) -> Result<()> {
+pub fn run_libfuzzer(target: &str, fuzzer: Fuzzer, options: &RunOptions) -> Result<()> {
     trace!("Getting ready to start fuzzing with Libfuzzer");
-    write_fuzz_target_source_file(&fuzzer, &target)?;
-    let artifact_dir = create_artifact_dir(fuzzer.directory(), target)?;
-    let corpus_dir = gen_corpus(&target, fuzzer, seeds)?;
-    //info!("Corpus generated!");
-
-    #[cfg(target_os = "macos")]
-    let target_platform = "x86_64-apple-darwin";
-    #[cfg(target_os = "linux")]
-    let target_platform = "x86_64-unknown-linux-gnu";
-    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-    panic!("libfuzzer-sys only supports Linux and macOS");
-
-    // FIXME: The -C codegen-units=1 and -C incremental=..
-    // below seem to workaround some difficult issues in Rust nightly
-    // https://github.com/rust-lang/rust/issues/53945.
-    // If this is ever fixed remember to remove the fuzz-incremental
-    // entry from .gitignore.
-    let mut rust_flags = env::var("RUSTFLAGS").unwrap_or_default();
-    rust_flags.push_str(
-        "--cfg fuzzing -C codegen-units=1 -C incremental=fuzz-incremental -C passes=sancov -C \
-         llvm-args=-sanitizer-coverage-level=4 -C llvm-args=-sanitizer-coverage-trace-compares -C \
-         llvm-args=-sanitizer-coverage-inline-8bit-counters -C llvm-args=-sanitizer-coverage-trace-geps -C \
-         llvm-args=-sanitizer-coverage-prune-blocks=0 -C debug-assertions=on -C debuginfo=0 -C opt-level=3 -Z \
-         sanitizer=address",
-    );
+    let artifact_dir = options.artifact_dir(fuzzer, target)?;
+    let corpus_dir = options.corpus(fuzzer, target)?;
+    let fuzz_bin = build_target(target, fuzzer, false)?;
 
     let mut asan_options = env::var("ASAN_OPTIONS").unwrap_or_default();
     asan_options.push_str(" detect_odr_violation=0");
 
-    let fuzzer_bin = Command::new("cargo")
-        .args(&["run", "--target", &target_platform, "--bin", target, "--"])
+    let timeout_arg = match options.timeout_ms {
+        0 => None,
+        _ => Some(format!("-timeout={}", options.timeout_secs())),
+    };
+    let runs_arg = match options.runs {
+        0 => None,
+        runs => Some(format!("-runs={}", runs)),
+    };
+
+    catch_interrupts()?;
+    let engine = Command::new(&fuzz_bin)
         .arg(&corpus_dir)
-        .arg(&artifact_dir)
-        .env("RUSTFLAGS", &rust_flags)
+        .arg(format!("-artifact_prefix={}/", artifact_dir.display()))
+        .arg(format!("-rss_limit_mb={}", options.rss_limit_mb))
+        .args(timeout_arg)
+        .args(runs_arg)
+        .env(ALLOC_BUDGET_ENV, options.alloc_budget_mb.to_string())
         .env("ASAN_OPTIONS", &asan_options)
         .current_dir(fuzzer.directory())
         .spawn()
-        .context(format!("Failed to run {}", fuzzer))?
-        .wait()
-        .context(format!("Failed to wait {}", fuzzer))?;
-
-    if !fuzzer_bin.success() {
-        return Err(anyhow!(
-            "{} exited with code {:?}",
-            fuzzer,
-            fuzzer_bin.code()
-        ));
-    }
-
-    Ok(())
+        .context(format!("Failed to run {}", fuzzer))?;
+
+    wait_for_engine(
+        fuzzer,
+        engine,
+        &artifact_dir,
+        &options.hangs_dir(fuzzer, target),
+    )
 }
-/// Run one target fuzz test using Libfuzzer
-pub fn build_libfuzzer(target: &str, fuzzer: Fuzzer, artifacts: &str) -> Result<()> {
-    trace!("Regression tests with LibFuzzer");
-    write_fuzz_target_source_file(&fuzzer, &target)?;
-    //let artifact_dir = artifacts;
-    //let corpus_dir = gen_corpus(&target, fuzzer, seeds)?;
-    //info!("Corpus generated!");
-
-    #[cfg(target_os = "macos")]
-    let target_platform = "x86_64-apple-darwin";
-    #[cfg(target_os = "linux")]
-    let target_platform = "x86_64-unknown-linux-gnu";
-    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-    panic!("libfuzzer-sys only supports Linux and macOS");
-
-    // FIXME: The -C codegen-units=1 and -C incremental=..
-    // below seem to workaround some difficult issues in Rust nightly
-    // https://github.com/rust-lang/rust/issues/53945.
-    // If this is ever fixed remember to remove the fuzz-incremental
-    // entry from .gitignore.
-    let mut rust_flags = env::var("RUSTFLAGS").unwrap_or_default();
-    rust_flags.push_str(
-        "--cfg fuzzing -C codegen-units=1 -C incremental=fuzz-incremental -C passes=sancov -C \
-         llvm-args=-sanitizer-coverage-level=4 -C llvm-args=-sanitizer-coverage-trace-compares -C \
-         llvm-args=-sanitizer-coverage-inline-8bit-counters -C llvm-args=-sanitizer-coverage-trace-geps -C \
-         llvm-args=-sanitizer-coverage-prune-blocks=0 -C debug-assertions=on -C debuginfo=0 -C opt-level=3 -Z \
-         sanitizer=address",
-    );
 
-    let mut asan_options = env::var("ASAN_OPTIONS").unwrap_or_default();
-    asan_options.push_str(" detect_odr_violation=0");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let fuzzer_bin = Command::new("cargo")
-        .args(&["run", "--target", &target_platform, "--bin", target, "--"])
-        //.arg(&corpus_dir)
-        .arg(&artifacts)
-        //.arg("-runs=0")
-        .env("RUSTFLAGS", &rust_flags)
-        .env("ASAN_OPTIONS", &asan_options)
-        .current_dir(fuzzer.directory())
-        .spawn()
-        .context(format!("Failed to run {}", fuzzer))?
-        .wait()
-        .context(format!("Failed to wait {}", fuzzer))?;
+    fn libfuzzer_manifest() -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzzer-libfuzzer/Cargo.toml");
+        fs::read_to_string(&path).unwrap()
+    }
 
-    if !fuzzer_bin.success() {
-        return Err(anyhow!(
-            "{} exited with code {:?}",
-            fuzzer,
-            fuzzer_bin.code()
-        ));
+    #[test]
+    fn cargo_fuzz_bins_match_targets() {
+        let manifest: toml::Value = toml::from_str(&libfuzzer_manifest()).unwrap();
+        let bins = manifest["bin"].as_array().unwrap();
+        let names: Vec<&str> = bins
+            .iter()
+            .map(|bin| bin["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, *TARGETS);
+        for bin in bins {
+            let name = bin["name"].as_str().unwrap();
+            assert_eq!(
+                bin["path"].as_str().unwrap(),
+                target_source_path(Fuzzer::Libfuzzer, name)
+            );
+            assert_eq!(
+                bin.get("required-features").is_some(),
+                DIFF_TARGETS.contains(&name),
+                "{} has the wrong required-features",
+                name
+            );
+        }
     }
 
-    Ok(())
+    #[test]
+    fn cargo_fuzz_manifest_is_in_sync() {
+        let current = libfuzzer_manifest();
+        assert_eq!(
+            cargo_fuzz_manifest(&current),
+            current,
+            "run `cargo run cargo-fuzz sync`"
+        );
+    }
 }
@@ -0,0 +1,312 @@
+//! Triage of the inputs that timed out while fuzzing.
+//!
+//! Each hang is replayed against the engine's binary and its stack is sampled with gdb. The
+//! function found on top of the stack most often is where the input spends its time.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    engines::Fuzzer,
+    runner::{engine_binary, hangs_dir, pre_check},
+};
+use anyhow::{anyhow, Context, Result};
+use log::{info, trace, warn};
+
+/// Frames from the standard library, the allocator and the engines' runtimes, which say
+/// nothing about which target code is slow
+const RUNTIME_PREFIXES: &[&str] = &[
+    "core::",
+    "<core::",
+    "alloc::",
+    "<alloc::",
+    "std::",
+    "<std::",
+    "__",
+    "??",
+    "malloc",
+    "free",
+    "realloc",
+    "calloc",
+    "mem",
+    "fuzzer::",
+    "libfuzzer_sys::",
+    "afl::",
+    "honggfuzz::",
+    "libafl::",
+    "libafl_targets::",
+];
+
+/// The function named by one frame of a gdb backtrace, e.g.
+/// `#1  0x000055555556d1a4 in tari_mmr::mmr::MerkleMountainRange<D,B>::validate (self=...) at ...`
+fn frame_function(line: &str) -> Option<&str> {
+    let frame = line.trim_start().strip_prefix('#')?;
+    let frame = frame
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim();
+    let frame = match frame.find(" in ") {
+        Some(pos) if frame.starts_with("0x") => &frame[pos + 4..],
+        _ => frame,
+    };
+    // The arguments start at the first ` (` outside of generic parameters, which may hold tuples
+    let mut depth = 0usize;
+    for (pos, c) in frame.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' if !frame[..pos].ends_with('-') => depth = depth.saturating_sub(1),
+            '(' if depth == 0 && frame[..pos].ends_with(' ') => return Some(frame[..pos].trim()),
+            _ => {}
+        }
+    }
+    Some(frame.trim())
+}
+
+/// The innermost frame that belongs to the target or the code under test
+fn top_frame(backtrace: &str) -> Option<String> {
+    backtrace
+        .lines()
+        .filter_map(frame_function)
+        .find(|function| !RUNTIME_PREFIXES.iter().any(|p| function.starts_with(p)))
+        .map(String::from)
+}
+
+/// Replay `input` and sample its stack every `interval` until it exits or `samples` are taken.
+///
+/// Returns how often each function was on top, most frequent first, or `None` when the input
+/// no longer hangs.
+fn sample_hang(
+    fuzzer: Fuzzer,
+    binary: &Path,
+    input: &Path,
+    samples: usize,
+    interval: Duration,
+) -> Result<Option<Vec<(String, usize)>>> {
+    let mut command = Command::new(binary);
+    match fuzzer {
+        Fuzzer::Afl => {
+            command.stdin(fs::File::open(input)?);
+        }
+        Fuzzer::Honggfuzz => {
+            command.env("CARGO_HONGGFUZZ_CRASH_FILENAME", input);
+        }
+        // Libfuzzer and LibAFL binaries take the input as an argument
+        _ => {
+            command.arg(input);
+        }
+    }
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context(format!("Failed to run {}", binary.display()))?;
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut hung = true;
+    for _ in 0..samples {
+        thread::sleep(interval);
+        if child.try_wait()?.is_some() {
+            hung = false;
+            break;
+        }
+        let backtrace = Command::new("gdb")
+            .args(&["-p", &child.id().to_string(), "-batch", "-ex", "bt"])
+            .stderr(Stdio::null())
+            .output()
+            .context("Failed to run gdb")?;
+        if let Some(function) = top_frame(&String::from_utf8_lossy(&backtrace.stdout)) {
+            *counts.entry(function).or_insert(0) += 1;
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    if !hung {
+        return Ok(None);
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(Some(counts))
+}
+
+/// Replay every hang saved for `target` and report the slowest function of each. Hangs are
+/// looked up in `artifacts_dir` when the run was given one with `--artifacts-dir`.
+pub fn triage_hangs(
+    target: &str,
+    fuzzer: Fuzzer,
+    artifacts_dir: Option<&Path>,
+    samples: usize,
+    interval_ms: u64,
+) -> Result<()> {
+    trace!("Triaging hangs for {}", target);
+    let binary = match engine_binary(fuzzer, target) {
+        Some(binary) => binary,
+        None => {
+            return Err(anyhow!(
+                "the in-process engine has no separate binary to sample, replay its hangs from {} \
+                 with another engine",
+                hangs_dir(fuzzer, target, artifacts_dir).display()
+            ))
+        }
+    };
+    pre_check(
+        Command::new("gdb").arg("--version"),
+        "apt install gdb (attaching also needs ptrace permission)",
+    )?;
+    if !binary.exists() {
+        return Err(anyhow!(
+            "{} not found, fuzz {} with {} first",
+            binary.display(),
+            target,
+            fuzzer
+        ));
+    }
+
+    let hangs_dir = hangs_dir(fuzzer, target, artifacts_dir);
+    let mut hangs: Vec<_> = match fs::read_dir(&hangs_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    if hangs.is_empty() {
+        info!("No hangs saved in {:?}", &hangs_dir);
+        return Ok(());
+    }
+    hangs.sort();
+
+    let interval = Duration::from_millis(interval_ms);
+    for hang in &hangs {
+        match sample_hang(fuzzer, &binary, hang, samples, interval)? {
+            None => warn!("{:?} did not hang on replay", hang),
+            Some(counts) if counts.is_empty() => {
+                warn!("{:?} hangs, but no stack could be sampled", hang)
+            }
+            Some(counts) => {
+                let total: usize = counts.iter().map(|(_, n)| n).sum();
+                println!("\u{23F3} {}", hang.display());
+                println!(
+                    "   slowest: {} ({}/{} samples)",
+                    counts[0].0, counts[0].1, total
+                );
+                for (function, n) in counts.iter().skip(1).take(4) {
+                    println!("            {} ({}/{} samples)", function, n, total);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_function_parses_gdb_frames() {
+        let frames = [
+            (
+                "#0  0x00005555556a3f10 in tari_mmr::merkle_mountain_range::MerkleMountainRange<D,B>::get_merkle_root (self=0x7fffffffd2a0) at /root/tari/base_layer/mmr/src/merkle_mountain_range.rs:108",
+                "tari_mmr::merkle_mountain_range::MerkleMountainRange<D,B>::get_merkle_root",
+            ),
+            // Inlined frames have no address
+            (
+                "#2  alloc::vec::Vec<T,A>::extend_from_slice<u8,alloc::alloc::Global> (self=0x7fffffffc8f0, other=...) at /rustc/2fd73fabe469357a12c2c974c140f67e7cdd76d0/library/alloc/src/vec/mod.rs:2324",
+                "alloc::vec::Vec<T,A>::extend_from_slice<u8,alloc::alloc::Global>",
+            ),
+            (
+                "#4  0x000055555567c3a1 in <tari_mmr::mutable_mmr::MutableMmr<D,B> as core::clone::Clone>::clone (self=0x7fffffffd010) at /root/tari/base_layer/mmr/src/mutable_mmr.rs:40",
+                "<tari_mmr::mutable_mmr::MutableMmr<D,B> as core::clone::Clone>::clone",
+            ),
+            (
+                "#5  0x000055555566b2a4 in fuzz_targets::tari_core_mmr::mmr_mutable::{{closure}} (leaf=...) at fuzz_targets/tari_core_mmr.rs:88",
+                "fuzz_targets::tari_core_mmr::mmr_mutable::{{closure}}",
+            ),
+            (
+                "#3  0x0000555555671c0e in core::ptr::drop_in_place<std::collections::hash::map::HashMap<u64, (u64, u64), std::collections::hash::map::RandomState>> () at /rustc/2fd73fabe469357a12c2c974c140f67e7cdd76d0/library/core/src/ptr/mod.rs:487",
+                "core::ptr::drop_in_place<std::collections::hash::map::HashMap<u64, (u64, u64), std::collections::hash::map::RandomState>>",
+            ),
+            (
+                "#1  0x00005555556b12c0 in <fn() -> u64 as core::ops::function::FnOnce<()>>::call_once () at /rustc/2fd73fabe469357a12c2c974c140f67e7cdd76d0/library/core/src/ops/function.rs:250",
+                "<fn() -> u64 as core::ops::function::FnOnce<()>>::call_once",
+            ),
+            (
+                "#0  __memmove_avx_unaligned_erms () at ../sysdeps/x86_64/multiarch/memmove-vec-unaligned-erms.S:314",
+                "__memmove_avx_unaligned_erms",
+            ),
+            (
+                "#0  0x00007ffff7d1e9a5 in ?? () from /lib/x86_64-linux-gnu/libc.so.6",
+                "??",
+            ),
+            (
+                "#8  0x00005555555f8a43 in fuzzer::Fuzzer::ExecuteCallback(unsigned char const*, unsigned long) ()",
+                "fuzzer::Fuzzer::ExecuteCallback(unsigned char const*, unsigned long)",
+            ),
+        ];
+        for (line, function) in &frames {
+            assert_eq!(frame_function(line), Some(*function), "{}", line);
+        }
+    }
+
+    #[test]
+    fn frame_function_ignores_other_output() {
+        for line in &[
+            "",
+            "[Thread debugging using libthread_db enabled]",
+            "Using host libthread_db library \"/lib/x86_64-linux-gnu/libthread_db.so.1\".",
+            "0x00005555556a3f10 in tari_mmr::merkle_mountain_range::MerkleMountainRange<D,B>::get_merkle_root ()",
+            "[Inferior 1 (process 4242) detached]",
+        ] {
+            assert_eq!(frame_function(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn top_frame_skips_runtime_frames() {
+        let backtrace = "\
+#0  __memmove_avx_unaligned_erms () at ../sysdeps/x86_64/multiarch/memmove-vec-unaligned-erms.S:314
+#1  0x00005555556b12c0 in core::intrinsics::copy_nonoverlapping<u8> (src=0x602000000010, dst=0x603000000040, count=64) at /rustc/2fd73fabe469357a12c2c974c140f67e7cdd76d0/library/core/src/intrinsics.rs:2096
+#2  alloc::vec::Vec<T,A>::extend_from_slice<u8,alloc::alloc::Global> (self=0x7fffffffc8f0, other=...) at /rustc/2fd73fabe469357a12c2c974c140f67e7cdd76d0/library/alloc/src/vec/mod.rs:2324
+#3  0x0000555555671c0e in <alloc::vec::Vec<T,A> as core::clone::Clone>::clone (self=0x7fffffffc9a8) at /rustc/2fd73fabe469357a12c2c974c140f67e7cdd76d0/library/alloc/src/vec/mod.rs:2631
+#4  0x000055555567c3a1 in <tari_mmr::mutable_mmr::MutableMmr<D,B> as core::clone::Clone>::clone (self=0x7fffffffd010) at /root/tari/base_layer/mmr/src/mutable_mmr.rs:40
+#5  0x000055555566b2a4 in fuzz_targets::tari_core_mmr::mmr_mutable::{{closure}} (leaf=...) at fuzz_targets/tari_core_mmr.rs:88
+#6  0x0000555555669e12 in fuzz_targets::tari_core_mmr::mmr_mutable (data=...) at fuzz_targets/tari_core_mmr.rs:80
+#7  0x00005555555f1b3d in libfuzzer_sys::test_input_wrap (data=0x602000000010, size=512) at /root/.cargo/registry/src/github.com-1ecc6299db9ec823/libfuzzer-sys-0.3.5/src/lib.rs:28
+#8  0x00005555555f8a43 in fuzzer::Fuzzer::ExecuteCallback(unsigned char const*, unsigned long) ()
+#9  0x00007ffff7c29d90 in __libc_start_call_main (main=main@entry=0x5555555f0a20 <main>, argc=argc@entry=2, argv=argv@entry=0x7fffffffe2c8) at ../sysdeps/nptl/libc_start_call_main.h:58
+";
+        assert_eq!(
+            top_frame(backtrace).as_deref(),
+            Some("<tari_mmr::mutable_mmr::MutableMmr<D,B> as core::clone::Clone>::clone")
+        );
+    }
+
+    #[test]
+    fn top_frame_skips_unresolved_frames() {
+        let backtrace = "\
+#0  0x00007ffff7d1e9a5 in ?? () from /lib/x86_64-linux-gnu/libc.so.6
+#1  0x00007ffff7d1f0c2 in ?? () from /lib/x86_64-linux-gnu/libc.so.6
+#2  0x000055555566b2a4 in fuzz_targets::tari_core_mmr::mmr_mutable::{{closure}} (leaf=...) at fuzz_targets/tari_core_mmr.rs:88
+";
+        assert_eq!(
+            top_frame(backtrace).as_deref(),
+            Some("fuzz_targets::tari_core_mmr::mmr_mutable::{{closure}}")
+        );
+    }
+
+    #[test]
+    fn top_frame_of_runtime_only_stack_is_none() {
+        let backtrace = "\
+#0  0x00007ffff7d1e9a5 in ?? () from /lib/x86_64-linux-gnu/libc.so.6
+#1  0x00007ffff7c8fa2e in malloc () from /lib/x86_64-linux-gnu/libc.so.6
+#2  0x00005555556a01f3 in std::sys::unix::thread::Thread::sleep (dur=...) at library/std/src/sys/unix/thread.rs:241
+#3  0x00005555555f1b3d in honggfuzz::fuzz (closure=...) at /root/.cargo/registry/src/github.com-1ecc6299db9ec823/honggfuzz-0.5.45/src/lib.rs:329
+";
+        assert_eq!(top_frame(backtrace), None);
+    }
+}
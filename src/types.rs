@@ -20,3 +20,11 @@ pub fn gen_i8(gen: &mut SeedGen) -> i8 {
     let data = gen.generate(proptest::arbitrary::any::<i8>());
     data
 }
+pub fn gen_array_u8_32(gen: &mut SeedGen) -> [u8; 32] {
+    let data = gen.generate(proptest::arbitrary::any::<[u8; 32]>());
+    data
+}
+pub fn gen_u64(gen: &mut SeedGen) -> u64 {
+    let data = gen.generate(proptest::arbitrary::any::<u64>());
+    data
+}